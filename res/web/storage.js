@@ -0,0 +1,34 @@
+// miniquad plugin backing `src/storage.rs` with the browser's local storage.
+// Load it after `mq_js_bundle.js` and before `load("rust-game.wasm")`.
+(function () {
+    const PREFIX = "breakdown.";
+
+    function read_string(ptr, len) {
+        const bytes = new Uint8Array(wasm_memory.buffer, ptr, len);
+        return new TextDecoder().decode(bytes);
+    }
+
+    function stored(key_ptr, key_len) {
+        return window.localStorage.getItem(PREFIX + read_string(key_ptr, key_len));
+    }
+
+    function register_plugin(importObject) {
+        importObject.env.breakdown_storage_len = function (key_ptr, key_len) {
+            const value = stored(key_ptr, key_len);
+            return value === null ? -1 : new TextEncoder().encode(value).length;
+        };
+        importObject.env.breakdown_storage_get = function (key_ptr, key_len, buf_ptr, buf_len) {
+            const value = new TextEncoder().encode(stored(key_ptr, key_len) || "");
+            new Uint8Array(wasm_memory.buffer, buf_ptr, buf_len).set(value.subarray(0, buf_len));
+        };
+        importObject.env.breakdown_storage_set = function (key_ptr, key_len, val_ptr, val_len) {
+            const key = PREFIX + read_string(key_ptr, key_len);
+            window.localStorage.setItem(key, read_string(val_ptr, val_len));
+        };
+        importObject.env.breakdown_storage_remove = function (key_ptr, key_len) {
+            window.localStorage.removeItem(PREFIX + read_string(key_ptr, key_len));
+        };
+    }
+
+    miniquad_add_plugin({ register_plugin, name: "breakdown_storage", version: 1 });
+})();
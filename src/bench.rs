@@ -0,0 +1,80 @@
+use crate::game::ball::Ball;
+use crate::game::input::InputSnapshot;
+use crate::game::level::Level;
+use crate::game::scale::Scale;
+use crate::game::timestep::DEFAULT_TICK_RATE;
+use crate::game::world::{World, BALL_SIZE, BALL_SPEED};
+use crate::{SCALE, SCREEN_SCALE_FACTOR};
+use macroquad::prelude::*;
+use std::time::Instant;
+
+const BENCH_SEED: u64 = 42;
+const BENCH_BALLS: usize = 500;
+const BENCH_STEPS: usize = 600;
+const BENCH_COLUMNS: usize = 20;
+const BENCH_ROWS: usize = 10;
+const BENCH_BLOCK_LIVES: i32 = 1_000_000;
+
+/// Runs the same crowded scenario with and without the collision broad phase
+/// and prints how long the simulation took, without opening a window.
+pub fn run_collision_benchmark() {
+    let bounds = Rect::new(0f32, 0f32, 800f32, 600f32);
+    let scale = Scale::for_width(SCALE, SCREEN_SCALE_FACTOR, bounds.w);
+    let source = format!(
+        "name: Benchmark\ngrid:\n{}",
+        format!("{}\n", "R".repeat(BENCH_COLUMNS)).repeat(BENCH_ROWS)
+    );
+    let level = Level::parse(&source).expect("benchmark level is valid");
+    let dt = 1f32 / DEFAULT_TICK_RATE;
+    println!(
+        "{} balls, {} blocks, {} steps",
+        BENCH_BALLS,
+        BENCH_COLUMNS * BENCH_ROWS,
+        BENCH_STEPS
+    );
+
+    for broad_phase in [false, true] {
+        let mut world = World::new(bounds, scale, &level, BENCH_SEED);
+        world.broad_phase = broad_phase;
+        // Blocks that never break keep the board full for the whole run.
+        for block in world.blocks.iter_mut() {
+            block.lives = BENCH_BLOCK_LIVES;
+        }
+        world.balls.clear();
+        for _ in 0..BENCH_BALLS {
+            let position = vec2(
+                world.rng.gen_range_f32(0f32, bounds.w - BALL_SIZE),
+                world
+                    .rng
+                    .gen_range_f32(bounds.h * 0.5f32, bounds.h * 0.8f32),
+            );
+            let ball = Ball::new(
+                position,
+                BALL_SIZE * scale.total_scale,
+                BALL_SPEED * scale.total_scale,
+                &mut world.rng,
+            );
+            world.balls.push(ball);
+        }
+
+        let input = InputSnapshot::default();
+        let mut ball_steps = 0;
+        let start = Instant::now();
+        for _ in 0..BENCH_STEPS {
+            ball_steps += world.balls.len();
+            world.step(&input, dt);
+            world.events.drain_published();
+        }
+        let elapsed = start.elapsed();
+        println!(
+            "{:<12} {:>8.1} ms  {:>6.0} ns/ball step  ({} balls left)",
+            match broad_phase {
+                true => "grid",
+                false => "brute force",
+            },
+            elapsed.as_secs_f64() * 1000f64,
+            elapsed.as_nanos() as f64 / ball_steps.max(1) as f64,
+            world.balls.len()
+        );
+    }
+}
@@ -0,0 +1,355 @@
+use crate::game::input::InputSnapshot;
+use crate::gamepad::{Gamepads, PadButton};
+use macroquad::prelude::*;
+
+/// Something the player can do, independent of the key or button used.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Action {
+    MoveLeft,
+    MoveRight,
+    Launch,
+    Fire,
+    Pause,
+    Confirm,
+    Back,
+}
+
+impl Action {
+    pub const ALL: [Action; 7] = [
+        Action::MoveLeft,
+        Action::MoveRight,
+        Action::Launch,
+        Action::Fire,
+        Action::Pause,
+        Action::Confirm,
+        Action::Back,
+    ];
+
+    /// The name used for this action in the settings file.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveLeft => "move_left",
+            Action::MoveRight => "move_right",
+            Action::Launch => "launch",
+            Action::Fire => "fire",
+            Action::Pause => "pause",
+            Action::Confirm => "confirm",
+            Action::Back => "back",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Action::ALL.into_iter().find(|action| action.name() == name)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            Action::MoveLeft => "Move left",
+            Action::MoveRight => "Move right",
+            Action::Launch => "Launch",
+            Action::Fire => "Fire",
+            Action::Pause => "Pause",
+            Action::Confirm => "Confirm",
+            Action::Back => "Back",
+        }
+    }
+
+    fn default_keys(&self) -> &'static [KeyCode] {
+        match self {
+            Action::MoveLeft => &[KeyCode::Left, KeyCode::A],
+            Action::MoveRight => &[KeyCode::Right, KeyCode::D],
+            Action::Launch => &[KeyCode::Space],
+            Action::Fire => &[KeyCode::Space],
+            Action::Pause => &[KeyCode::Escape, KeyCode::P],
+            Action::Confirm => &[KeyCode::Enter, KeyCode::KpEnter],
+            Action::Back => &[KeyCode::Escape],
+        }
+    }
+
+    /// Gamepad buttons are fixed: d-pad or left stick to move, A to launch
+    /// and confirm, X or the right trigger to fire, B to go back and Start to
+    /// pause.
+    fn pad_buttons(&self) -> &'static [PadButton] {
+        match self {
+            Action::MoveLeft => &[PadButton::Left],
+            Action::MoveRight => &[PadButton::Right],
+            Action::Launch => &[PadButton::Confirm],
+            Action::Fire => &[PadButton::Fire],
+            Action::Pause => &[PadButton::Start],
+            Action::Confirm => &[PadButton::Confirm],
+            Action::Back => &[PadButton::Back],
+        }
+    }
+}
+
+/// Keys that can be bound to actions.
+pub const BINDABLE_KEYS: [KeyCode; 80] = [
+    KeyCode::Space,
+    KeyCode::Apostrophe,
+    KeyCode::Comma,
+    KeyCode::Minus,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Key0,
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+    KeyCode::Semicolon,
+    KeyCode::Equal,
+    KeyCode::A,
+    KeyCode::B,
+    KeyCode::C,
+    KeyCode::D,
+    KeyCode::E,
+    KeyCode::F,
+    KeyCode::G,
+    KeyCode::H,
+    KeyCode::I,
+    KeyCode::J,
+    KeyCode::K,
+    KeyCode::L,
+    KeyCode::M,
+    KeyCode::N,
+    KeyCode::O,
+    KeyCode::P,
+    KeyCode::Q,
+    KeyCode::R,
+    KeyCode::S,
+    KeyCode::T,
+    KeyCode::U,
+    KeyCode::V,
+    KeyCode::W,
+    KeyCode::X,
+    KeyCode::Y,
+    KeyCode::Z,
+    KeyCode::LeftBracket,
+    KeyCode::Backslash,
+    KeyCode::RightBracket,
+    KeyCode::GraveAccent,
+    KeyCode::Escape,
+    KeyCode::Enter,
+    KeyCode::Tab,
+    KeyCode::Backspace,
+    KeyCode::Insert,
+    KeyCode::Delete,
+    KeyCode::Right,
+    KeyCode::Left,
+    KeyCode::Down,
+    KeyCode::Up,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::Kp0,
+    KeyCode::Kp1,
+    KeyCode::Kp2,
+    KeyCode::Kp3,
+    KeyCode::Kp4,
+    KeyCode::Kp5,
+    KeyCode::Kp6,
+    KeyCode::Kp7,
+    KeyCode::Kp8,
+    KeyCode::Kp9,
+    KeyCode::KpEnter,
+    KeyCode::LeftShift,
+    KeyCode::LeftControl,
+    KeyCode::LeftAlt,
+    KeyCode::RightShift,
+    KeyCode::RightControl,
+    KeyCode::RightAlt,
+    KeyCode::CapsLock,
+];
+
+pub fn key_name(key: KeyCode) -> String {
+    format!("{:?}", key)
+}
+
+pub fn key_from_name(name: &str) -> Option<KeyCode> {
+    BINDABLE_KEYS.into_iter().find(|key| key_name(*key) == name)
+}
+
+/// The keys bound to each action.
+#[derive(Clone, PartialEq)]
+pub struct KeyBindings {
+    // Indexed like `Action::ALL`
+    keys: Vec<Vec<KeyCode>>,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        Self {
+            keys: Action::ALL
+                .iter()
+                .map(|action| action.default_keys().to_vec())
+                .collect(),
+        }
+    }
+}
+
+impl KeyBindings {
+    pub fn keys(&self, action: Action) -> &[KeyCode] {
+        &self.keys[action as usize]
+    }
+
+    pub fn set(&mut self, action: Action, keys: Vec<KeyCode>) {
+        self.keys[action as usize] = keys;
+    }
+
+    /// The bound keys as shown to the player, e.g. `Left, A`.
+    pub fn describe(&self, action: Action) -> String {
+        match self.keys(action).is_empty() {
+            true => String::from("none"),
+            false => self
+                .keys(action)
+                .iter()
+                .map(|key| key_name(*key))
+                .collect::<Vec<_>>()
+                .join(", "),
+        }
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum InputMethod {
+    /// Keyboard or gamepad.
+    Keyboard,
+    /// Mouse or touch screen.
+    Pointer,
+}
+
+/// Turns the keyboard, gamepads, mouse and touch screen into actions and
+/// gameplay input.
+pub struct Controls {
+    pub bindings: KeyBindings,
+    pub gamepads: Gamepads,
+    pub method: InputMethod,
+    last_mouse: Vec2,
+    /// Actions held through a screen change, ignored until they are let go.
+    consumed: Vec<Action>,
+}
+
+impl Controls {
+    pub fn new() -> Self {
+        Self {
+            bindings: KeyBindings::default(),
+            gamepads: Gamepads::new(),
+            method: InputMethod::Keyboard,
+            last_mouse: Vec2::from(mouse_position()),
+            consumed: Vec::new(),
+        }
+    }
+
+    /// Polls the gamepads; call once at the start of every frame.
+    pub fn update(&mut self) {
+        self.gamepads.update();
+        // Consumed actions stay ignored through the frame they are let go.
+        let consumed = std::mem::take(&mut self.consumed);
+        self.consumed = consumed
+            .into_iter()
+            .filter(|action| self.is_down(*action) || self.is_released(*action))
+            .collect();
+    }
+
+    /// Ignores every action held right now until it is let go, so a key held
+    /// while the screen changes doesn't also act on the new screen.
+    pub fn consume(&mut self) {
+        self.consumed = Action::ALL
+            .into_iter()
+            .filter(|action| self.is_down(*action))
+            .collect();
+    }
+
+    fn is_down(&self, action: Action) -> bool {
+        self.bindings
+            .keys(action)
+            .iter()
+            .any(|key| is_key_down(*key))
+            || action
+                .pad_buttons()
+                .iter()
+                .any(|button| self.gamepads.held(*button))
+    }
+
+    fn is_released(&self, action: Action) -> bool {
+        self.bindings
+            .keys(action)
+            .iter()
+            .any(|key| is_key_released(*key))
+            || action
+                .pad_buttons()
+                .iter()
+                .any(|button| self.gamepads.released(*button))
+    }
+
+    /// Whether a key or button for `action` is held down.
+    pub fn held(&self, action: Action) -> bool {
+        !self.consumed.contains(&action) && self.is_down(action)
+    }
+
+    /// Whether a key or button for `action` went down this frame.
+    pub fn pressed(&self, action: Action) -> bool {
+        !self.consumed.contains(&action)
+            && (self
+                .bindings
+                .keys(action)
+                .iter()
+                .any(|key| is_key_pressed(*key))
+                || action
+                    .pad_buttons()
+                    .iter()
+                    .any(|button| self.gamepads.pressed(*button)))
+    }
+
+    /// Whether a key or button for `action` went up this frame, after being
+    /// pressed on the current screen.
+    pub fn released(&self, action: Action) -> bool {
+        !self.consumed.contains(&action) && self.is_released(action)
+    }
+
+    /// Whether the screen was tapped or clicked this frame while playing with
+    /// the pointer.
+    pub fn tapped(&self) -> bool {
+        self.method == InputMethod::Pointer
+            && match touches().first() {
+                Some(touch) => touch.phase == TouchPhase::Started,
+                None => is_mouse_button_pressed(MouseButton::Left),
+            }
+    }
+
+    /// Reads the gameplay input, steering with whichever of the keyboard or
+    /// gamepad and the pointer was used last.
+    pub fn capture(&mut self) -> InputSnapshot {
+        let left = self.held(Action::MoveLeft);
+        let right = self.held(Action::MoveRight);
+        let mouse = Vec2::from(mouse_position());
+        let touch = touches().into_iter().next();
+
+        if left || right {
+            self.method = InputMethod::Keyboard;
+        } else if mouse != self.last_mouse || touch.is_some() {
+            self.method = InputMethod::Pointer;
+        }
+        self.last_mouse = mouse;
+
+        let pointer = self.method == InputMethod::Pointer;
+        let tapped = self.tapped();
+        let held = touch.is_some() || is_mouse_button_down(MouseButton::Left);
+        InputSnapshot {
+            left,
+            right,
+            launch: self.held(Action::Launch) || tapped,
+            launch_pressed: self.pressed(Action::Launch) || tapped,
+            fire: self.held(Action::Fire) || (pointer && held),
+            pointer_x: match pointer {
+                true => Some(touch.map_or(mouse.x, |touch| touch.position.x)),
+                false => None,
+            },
+        }
+    }
+}
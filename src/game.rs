@@ -1,5 +1,18 @@
 pub mod ball;
 pub mod block;
+pub mod collision;
+pub mod drops;
+pub mod events;
+pub mod grid;
+pub mod input;
+pub mod level;
+pub mod particles;
 pub mod player;
+pub mod projectile;
+pub mod replay;
+pub mod rng;
 pub mod scale;
+pub mod snapshot;
+pub mod timestep;
 pub mod upgrades;
+pub mod world;
@@ -1,83 +1,260 @@
+use crate::game::rng::Rng;
+use crate::game::timestep::interpolate;
 use macroquad::prelude::*;
 
+// Largest angle from vertical a ball can leave the paddle at, so it never
+// goes near-horizontal.
+const MAX_PADDLE_ANGLE: f32 = 60f32 * std::f32::consts::PI / 180f32;
+// Extra angle, as a fraction of MAX_PADDLE_ANGLE, when the paddle moves at the
+// same speed as the ball.
+const PADDLE_VELOCITY_INFLUENCE: f32 = 0.3;
+
+#[derive(Clone, Copy)]
+pub struct BounceModel {
+    /// Random rotation in radians, applied evenly in both directions on each
+    /// bounce off walls and blocks. Zero disables it.
+    pub jitter: f32,
+    /// Smallest angle in radians between the ball path and the horizontal.
+    pub min_angle: f32,
+}
+
+impl Default for BounceModel {
+    fn default() -> Self {
+        Self {
+            jitter: 2f32.to_radians(),
+            min_angle: 15f32.to_radians(),
+        }
+    }
+}
+
+impl BounceModel {
+    /// Steepens near-horizontal directions up to `min_angle`, keeping the
+    /// horizontal and vertical directions the ball travels in.
+    fn limit_angle(&self, vel: Vec2) -> Vec2 {
+        let vel = vel.normalize();
+        let min_y = self.min_angle.sin();
+        if vel.y.abs() >= min_y {
+            return vel;
+        }
+        let sign_y = if vel.y < 0f32 { -1f32 } else { 1f32 };
+        let sign_x = if vel.x < 0f32 { -1f32 } else { 1f32 };
+        vec2(sign_x * (1f32 - min_y * min_y).sqrt(), sign_y * min_y)
+    }
+}
+
+/// What happens to a ball that would go over the level's ball cap.
+#[derive(Clone, Copy, PartialEq)]
+pub enum BallCapPolicy {
+    /// Award `BALL_CAP_SCORE` points instead.
+    Score,
+    /// Merge it into a regular ball, turning that one into a super ball.
+    SuperBall,
+    /// Drop it.
+    Ignore,
+}
+
+impl BallCapPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "score" => Some(BallCapPolicy::Score),
+            "super_ball" => Some(BallCapPolicy::SuperBall),
+            "ignore" => Some(BallCapPolicy::Ignore),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Ball {
     pub rect: Rect,
+    pub prev_rect: Rect,
     pub vel: Vec2,
     pub speed: f32,
     pub super_ball: bool,
+    /// Horizontal offset from the paddle while held by the magnet.
+    pub caught_offset: Option<f32>,
+    pub caught_time: f32,
 }
 
 impl Ball {
-    pub fn new(position: Vec2, size: f32, speed: f32) -> Self {
+    pub fn new(position: Vec2, size: f32, speed: f32, rng: &mut Rng) -> Self {
+        let rect = Rect::new(position.x, position.y, size, size);
         Self {
-            rect: Rect::new(position.x, position.y, size, size),
-            vel: vec2(rand::gen_range(-1f32, 1f32), 1f32).normalize(),
+            rect,
+            prev_rect: rect,
+            vel: vec2(rng.gen_range_f32(-1f32, 1f32), 1f32).normalize(),
             speed,
             super_ball: false,
+            caught_offset: None,
+            caught_time: 0f32,
         }
     }
 
-    pub fn new_super_ball(position: Vec2, size: f32, speed: f32) -> Self {
+    pub fn new_super_ball(position: Vec2, size: f32, speed: f32, rng: &mut Rng) -> Self {
+        let rect = Rect::new(position.x, position.y, size, size);
         Self {
-            rect: Rect::new(position.x, position.y, size, size),
-            vel: vec2(rand::gen_range(-1f32, 1f32), 1f32).normalize(),
+            rect,
+            prev_rect: rect,
+            vel: vec2(rng.gen_range_f32(-1f32, 1f32), 1f32).normalize(),
             speed,
             super_ball: true,
+            caught_offset: None,
+            caught_time: 0f32,
         }
     }
 
-    pub fn update(&mut self, dt: f32) {
-        self.rect.x += self.vel.x * dt * self.speed;
-        self.rect.y += self.vel.y * dt * self.speed;
+    pub fn random_direction(&mut self, rng: &mut Rng) {
+        self.vel = vec2(rng.gen_range_f32(-1f32, 1f32), 1f32).normalize();
+    }
 
-        if self.rect.x < 0f32 {
-            self.vel.x = 1f32;
-        }
+    pub fn delta(&self, dt: f32) -> Vec2 {
+        self.vel * dt * self.speed
+    }
 
-        if self.rect.x + self.rect.w > screen_width() {
-            self.vel.x = -1f32;
+    /// Mirrors the velocity off a surface with the given face normal; corner
+    /// normals mirror both axes. The speed never changes.
+    pub fn reflect(&mut self, normal: Vec2, model: &BounceModel, rng: &mut Rng) {
+        if self.vel.x * normal.x < 0f32 {
+            self.vel.x = -self.vel.x;
         }
-
-        if self.rect.y < 0f32 {
-            self.vel.y = 1f32;
+        if self.vel.y * normal.y < 0f32 {
+            self.vel.y = -self.vel.y;
         }
-        self.vel = self.vel.normalize();
-    }
-
-    pub fn random_direction(&mut self) {
-        self.vel = vec2(rand::gen_range(-1f32, 1f32), 1f32).normalize();
-    }
-
-    pub fn bounce(&mut self, body: &Rect) -> bool {
-        if let Some(intersection) = self.rect.intersect(*body) {
-            let a_center = self.rect.point() + self.rect.size() * 0.5f32;
-            let b_center = body.point() + body.size() * 0.5f32;
-            let to = b_center - a_center;
-            let to_signum = to.signum();
-            match intersection.w > intersection.h {
-                true => {
-                    // Bounce on y
-                    self.rect.y -= to_signum.y * intersection.h;
-                    self.vel.y = -to_signum.y * self.vel.y.abs();
-                    // Add some randomness to x
-                    self.vel.x += rand::gen_range(0f32, 0.2f32);
-                }
-                false => {
-                    // Bounce on x
-                    self.rect.x -= to_signum.x * intersection.w;
-                    self.vel.x = -to_signum.x * self.vel.x.abs();
-                }
+        if model.jitter > 0f32 {
+            let angle = rng.gen_range_f32(-model.jitter, model.jitter);
+            let jittered = Vec2::from_angle(angle).rotate(self.vel);
+            // Never let the jitter turn the ball back into the surface
+            if jittered.dot(normal) > 0f32 {
+                self.vel = jittered;
             }
-            return true;
         }
-        false
+        self.vel = model.limit_angle(self.vel);
+    }
+
+    /// Sends the ball back up at an angle that depends on where it hit the
+    /// paddle: straight up in the middle, steeper towards the edges.
+    pub fn deflect_off_paddle(&mut self, paddle: Rect, paddle_velocity: f32) {
+        let half_width = (paddle.w + self.rect.w) * 0.5f32;
+        let offset = (self.rect.center().x - paddle.center().x) / half_width;
+        let carry = paddle_velocity / self.speed * PADDLE_VELOCITY_INFLUENCE;
+        let angle = ((offset.clamp(-1f32, 1f32) + carry) * MAX_PADDLE_ANGLE)
+            .clamp(-MAX_PADDLE_ANGLE, MAX_PADDLE_ANGLE);
+        self.vel = vec2(angle.sin(), -angle.cos());
+    }
+
+    /// Sticks the ball to the top of the paddle where it touched it.
+    pub fn catch(&mut self, paddle: Rect, hold_time: f32) {
+        self.caught_offset = Some(self.rect.x - paddle.x);
+        self.caught_time = hold_time;
+        self.rect.y = paddle.top() - self.rect.h;
+    }
+
+    pub fn follow(&mut self, paddle: Rect) {
+        if let Some(offset) = self.caught_offset {
+            self.rect.x = paddle.x + offset;
+            self.rect.y = paddle.top() - self.rect.h;
+        }
     }
 
-    pub fn draw(&self) {
+    pub fn release(&mut self, paddle: Rect, paddle_velocity: f32) {
+        self.caught_offset = None;
+        self.deflect_off_paddle(paddle, paddle_velocity);
+    }
+
+    pub fn draw(&self, alpha: f32) {
         let color: Color = match self.super_ball {
             true => GOLD,
             false => BLUE,
         };
-        draw_rectangle(self.rect.x, self.rect.y, self.rect.w, self.rect.h, color);
+        let rect = interpolate(self.prev_rect, self.rect, alpha);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, color);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNCES: usize = 10_000;
+
+    fn test_ball(vel: Vec2) -> Ball {
+        let mut ball = Ball::new(Vec2::ZERO, 16f32, 320f32, &mut Rng::new(1));
+        ball.vel = vel.normalize();
+        ball
+    }
+
+    /// Bounces between walls, ceiling and floor like a ball in a box.
+    fn bounce_around(ball: &mut Ball, model: &BounceModel, rng: &mut Rng, check: impl Fn(&Ball)) {
+        for _ in 0..BOUNCES {
+            let normal = match ball.vel.x.abs() > ball.vel.y.abs() {
+                true => vec2(-ball.vel.x.signum(), 0f32),
+                false => vec2(0f32, -ball.vel.y.signum()),
+            };
+            ball.reflect(normal, model, rng);
+            check(ball);
+        }
+    }
+
+    #[test]
+    fn speed_stays_constant_with_jitter() {
+        let model = BounceModel {
+            jitter: 10f32.to_radians(),
+            ..Default::default()
+        };
+        let mut ball = test_ball(vec2(0.3, -1f32));
+        bounce_around(&mut ball, &model, &mut Rng::new(3), |ball| {
+            assert!((ball.vel.length() - 1f32).abs() < 1e-4);
+            assert_eq!(ball.speed, 320f32);
+        });
+    }
+
+    #[test]
+    fn bounce_angle_never_goes_below_min_angle() {
+        let model = BounceModel {
+            jitter: 10f32.to_radians(),
+            ..Default::default()
+        };
+        let min_y = model.min_angle.sin() - 1e-5;
+        // Starts almost horizontal
+        let mut ball = test_ball(vec2(1f32, 0.01));
+        bounce_around(&mut ball, &model, &mut Rng::new(5), |ball| {
+            assert!(ball.vel.y.abs() >= min_y);
+        });
+    }
+
+    #[test]
+    fn jitter_does_not_push_balls_to_one_side() {
+        let model = BounceModel::default();
+        let mut rng = Rng::new(9);
+        // Each bounce starts straight up, so any bias of the jitter shows up
+        // in the mean instead of being hidden in a random walk.
+        let mut sum_x = 0f32;
+        let mut sides = (0, 0);
+        for _ in 0..BOUNCES {
+            let mut ball = test_ball(vec2(0f32, -1f32));
+            ball.reflect(vec2(0f32, 1f32), &model, &mut rng);
+            sum_x += ball.vel.x;
+            match ball.vel.x < 0f32 {
+                true => sides.0 += 1,
+                false => sides.1 += 1,
+            }
+        }
+        let mean_x = sum_x / BOUNCES as f32;
+        assert!(mean_x.abs() < 1e-3, "mean vel.x {}", mean_x);
+        assert!(sides.0 > BOUNCES / 3 && sides.1 > BOUNCES / 3);
+    }
+
+    #[test]
+    fn bounces_without_jitter_do_not_drift() {
+        let model = BounceModel {
+            jitter: 0f32,
+            ..Default::default()
+        };
+        let start = vec2(0.3, -1f32).normalize();
+        let mut ball = test_ball(start);
+        bounce_around(&mut ball, &model, &mut Rng::new(7), |ball| {
+            assert!((ball.vel.x.abs() - start.x).abs() < 1e-6);
+            assert!((ball.vel.y.abs() - start.y.abs()).abs() < 1e-6);
+        });
     }
 }
@@ -1,39 +1,133 @@
+use crate::game::timestep::interpolate;
+use crate::game::upgrades::UpgradeType;
 use macroquad::prelude::*;
 
-#[derive(PartialEq)]
+#[derive(Clone, Copy, PartialEq)]
 pub enum BlockType {
     Regular,
     Upgrade,
+    /// Can't be destroyed and doesn't count towards clearing the level.
+    Steel,
+    /// Damages every block within its blast radius when destroyed.
+    Explosive,
+    /// Slides back and forth along its path.
+    Moving,
 }
 
+#[derive(Clone)]
 pub struct Block {
     pub rect: Rect,
+    pub prev_rect: Rect,
     pub lives: i32,
+    pub max_lives: i32,
     pub block_type: BlockType,
+    pub upgrade_type: Option<UpgradeType>,
+    /// Leftmost and rightmost x position of a moving block.
+    pub path: Option<(f32, f32)>,
+    pub velocity: f32,
 }
 
 impl Block {
-    pub fn new(pos: Vec2, block_type: BlockType, block_size: f32) -> Self {
+    pub fn new(pos: Vec2, block_type: BlockType, block_size: f32, lives: i32) -> Self {
+        let rect = Rect::new(pos.x, pos.y, block_size, block_size);
         Self {
-            rect: Rect::new(pos.x, pos.y, block_size, block_size),
-            lives: 2,
+            rect,
+            prev_rect: rect,
+            lives,
+            max_lives: lives,
             block_type,
+            upgrade_type: None,
+            path: None,
+            velocity: 0f32,
         }
     }
 
-    pub fn draw(&self) {
+    pub fn is_breakable(&self) -> bool {
+        self.block_type != BlockType::Steel
+    }
+
+    /// Points awarded for destroying this block.
+    pub fn score(&self) -> i32 {
+        match self.block_type {
+            BlockType::Regular | BlockType::Upgrade => 5 * self.max_lives,
+            BlockType::Explosive => 25,
+            BlockType::Moving => 15 * self.max_lives,
+            BlockType::Steel => 0,
+        }
+    }
+
+    /// Takes one life, or all of them for a super ball hit, and returns
+    /// whether this hit destroyed the block.
+    pub fn hit(&mut self, super_ball: bool) -> bool {
+        if self.lives <= 0 || !self.is_breakable() {
+            return false;
+        }
+        if super_ball {
+            self.lives = 0;
+        } else {
+            self.lives -= 1;
+        }
+        self.lives <= 0
+    }
+
+    /// Moves a moving block along its path, turning around at either end.
+    pub fn update(&mut self, dt: f32) {
+        self.prev_rect = self.rect;
+        let Some((min_x, max_x)) = self.path else {
+            return;
+        };
+        self.rect.x += self.velocity * dt;
+        if self.rect.x <= min_x {
+            self.rect.x = min_x;
+            self.velocity = self.velocity.abs();
+        }
+        if self.rect.x >= max_x {
+            self.rect.x = max_x;
+            self.velocity = -self.velocity.abs();
+        }
+    }
+
+    pub fn draw(&self, alpha: f32) {
+        let rect = interpolate(self.prev_rect, self.rect, alpha);
         let color = match self.block_type {
             BlockType::Regular => match self.lives {
+                5.. => DARKPURPLE,
+                4 => MAROON,
+                3 => BROWN,
                 2 => RED,
                 1 => ORANGE,
                 _ => BLACK,
             },
             BlockType::Upgrade => match self.lives {
+                3.. => DARKGREEN,
                 2 => GREEN,
                 1 => LIME,
                 _ => BLACK,
             },
+            BlockType::Steel => GRAY,
+            BlockType::Explosive => YELLOW,
+            BlockType::Moving => match self.lives {
+                2.. => DARKBLUE,
+                1 => SKYBLUE,
+                _ => BLACK,
+            },
         };
-        draw_rectangle(self.rect.x, self.rect.y, self.rect.w, self.rect.h, color);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, color);
+        match self.block_type {
+            BlockType::Steel => {
+                draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, 3f32, DARKGRAY)
+            }
+            BlockType::Explosive => {
+                let core = rect.w * 0.4f32;
+                draw_rectangle(
+                    rect.x + (rect.w - core) * 0.5f32,
+                    rect.y + (rect.h - core) * 0.5f32,
+                    core,
+                    core,
+                    RED,
+                );
+            }
+            _ => {}
+        }
     }
 }
@@ -0,0 +1,163 @@
+use macroquad::math::{vec2, Rect, Vec2};
+
+// Fraction of a movement within which entering both axes counts as a corner hit.
+const CORNER_TOLERANCE: f32 = 1e-3;
+
+#[derive(Clone, Copy)]
+pub struct Hit {
+    /// Fraction of the movement, in `0..=1`, at which the contact happens.
+    pub time: f32,
+    /// Face normal of the target; both components are set on exact corner hits.
+    pub normal: Vec2,
+}
+
+/// Sweeps `moving` along `delta` against the static `target` and returns the
+/// earliest contact within the movement.
+///
+/// A rect that already overlaps the target and keeps moving into it reports a
+/// hit at time 0, so it can be pushed back out instead of tunnelling further.
+pub fn sweep(moving: Rect, delta: Vec2, target: Rect) -> Option<Hit> {
+    // Minkowski sum: sweep the top-left corner of `moving` as a ray against
+    // the target grown by the moving rect's size.
+    let expanded = Rect::new(
+        target.x - moving.w,
+        target.y - moving.h,
+        target.w + moving.w,
+        target.h + moving.h,
+    );
+    let (entry_x, exit_x) = slab(moving.x, delta.x, expanded.left(), expanded.right())?;
+    let (entry_y, exit_y) = slab(moving.y, delta.y, expanded.top(), expanded.bottom())?;
+
+    let entry = entry_x.max(entry_y);
+    let exit = exit_x.min(exit_y);
+    if entry >= exit || exit <= 0f32 || entry > 1f32 {
+        return None;
+    }
+    if entry < 0f32 {
+        return resolve_overlap(moving, delta, target);
+    }
+
+    // Entering both slabs at (almost) the same time means the rect hit the
+    // target's corner, so the normal gets both axes.
+    let mut normal = Vec2::ZERO;
+    if entry_x >= entry_y - CORNER_TOLERANCE {
+        normal.x = -delta.x.signum();
+    }
+    if entry_y >= entry_x - CORNER_TOLERANCE {
+        normal.y = -delta.y.signum();
+    }
+    // Drop axes the rect isn't moving along, so a face hit never picks up a
+    // spurious corner normal.
+    if delta.x == 0f32 {
+        normal.x = 0f32;
+    }
+    if delta.y == 0f32 {
+        normal.y = 0f32;
+    }
+    if normal == Vec2::ZERO {
+        return None;
+    }
+
+    Some(Hit {
+        time: entry,
+        normal,
+    })
+}
+
+/// Pushes out along the axis of least penetration, but only while the rect
+/// is still moving into the target.
+fn resolve_overlap(moving: Rect, delta: Vec2, target: Rect) -> Option<Hit> {
+    let overlap = moving.intersect(target)?;
+    let away = moving.center() - target.center();
+    let normal = match overlap.w < overlap.h {
+        true => vec2(away.x.signum(), 0f32),
+        false => vec2(0f32, away.y.signum()),
+    };
+    if normal.dot(delta) >= 0f32 {
+        return None;
+    }
+    Some(Hit { time: 0f32, normal })
+}
+
+fn slab(origin: f32, delta: f32, min: f32, max: f32) -> Option<(f32, f32)> {
+    if delta == 0f32 {
+        if origin <= min || origin >= max {
+            return None;
+        }
+        return Some((f32::NEG_INFINITY, f32::INFINITY));
+    }
+    let t1 = (min - origin) / delta;
+    let t2 = (max - origin) / delta;
+    Some((t1.min(t2), t1.max(t2)))
+}
+
+/// Solid rects just outside the left, right and top edges of `bounds`; the
+/// bottom is left open so balls can fall out.
+pub fn walls(bounds: Rect) -> [Rect; 3] {
+    let thickness = bounds.w.max(bounds.h);
+    [
+        Rect::new(
+            bounds.left() - thickness,
+            bounds.top() - thickness,
+            thickness,
+            bounds.h + thickness * 2f32,
+        ),
+        Rect::new(
+            bounds.right(),
+            bounds.top() - thickness,
+            thickness,
+            bounds.h + thickness * 2f32,
+        ),
+        Rect::new(
+            bounds.left() - thickness,
+            bounds.top() - thickness,
+            bounds.w + thickness * 2f32,
+            thickness,
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fast_ball_hits_thin_block_instead_of_tunnelling() {
+        let block = Rect::new(100f32, 100f32, 200f32, 2f32);
+        let ball = Rect::new(150f32, 200f32, 16f32, 16f32);
+        // Far past the block within a single step
+        let delta = vec2(0f32, -10_000f32);
+
+        let hit = sweep(ball, delta, block).expect("the ball hits the block");
+        assert_eq!(hit.normal, vec2(0f32, 1f32));
+        let contact = ball.offset(delta * hit.time);
+        assert!((contact.top() - block.bottom()).abs() < 1e-2);
+    }
+
+    #[test]
+    fn fast_diagonal_ball_hits_thin_block() {
+        let block = Rect::new(400f32, 0f32, 2f32, 300f32);
+        let ball = Rect::new(0f32, 100f32, 16f32, 16f32);
+        let delta = vec2(5_000f32, 500f32);
+
+        let hit = sweep(ball, delta, block).expect("the ball hits the block");
+        assert_eq!(hit.normal, vec2(-1f32, 0f32));
+        assert!(ball.offset(delta * hit.time).right() <= block.left() + 1e-2);
+    }
+
+    #[test]
+    fn ball_moving_away_or_past_misses() {
+        let block = Rect::new(100f32, 100f32, 200f32, 2f32);
+        let ball = Rect::new(150f32, 200f32, 16f32, 16f32);
+        assert!(sweep(ball, vec2(0f32, 10_000f32), block).is_none());
+        assert!(sweep(ball, vec2(10_000f32, 0f32), block).is_none());
+    }
+
+    #[test]
+    fn exact_corner_hit_reports_both_axes() {
+        let block = Rect::new(100f32, 100f32, 50f32, 50f32);
+        let ball = Rect::new(50f32, 50f32, 10f32, 10f32);
+        let hit = sweep(ball, vec2(100f32, 100f32), block).expect("the ball hits the corner");
+        assert_eq!(hit.normal, vec2(-1f32, -1f32));
+    }
+}
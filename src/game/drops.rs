@@ -0,0 +1,179 @@
+use crate::game::rng::Rng;
+use crate::game::upgrades::UpgradeType;
+
+/// Decides which upgrades destroyed blocks drop.
+#[derive(Clone)]
+pub struct DropTable {
+    pub weights: Vec<(UpgradeType, u32)>,
+    /// Chance that a regular block drops an upgrade.
+    pub regular_drop_chance: f32,
+    /// Chance that an upgrade block without a fixed upgrade drops one.
+    pub upgrade_drop_chance: f32,
+}
+
+impl Default for DropTable {
+    fn default() -> Self {
+        Self {
+            weights: vec![
+                (UpgradeType::AddBall, 4),
+                (UpgradeType::BallMultiplier, 3),
+                (UpgradeType::Magnet, 3),
+                (UpgradeType::SuperBall, 2),
+                (UpgradeType::SpaceInvader, 2),
+                (UpgradeType::ExtraLife, 1),
+                (UpgradeType::ExpandPaddle, 2),
+                (UpgradeType::ShrinkPaddle, 1),
+                (UpgradeType::FastBall, 1),
+                (UpgradeType::ReverseControls, 1),
+                (UpgradeType::Fog, 1),
+                (UpgradeType::Death, 1),
+            ],
+            regular_drop_chance: 0f32,
+            upgrade_drop_chance: 1f32,
+        }
+    }
+}
+
+impl DropTable {
+    pub fn set_weight(&mut self, upgrade_type: UpgradeType, weight: u32) {
+        match self.weights.iter_mut().find(|(t, _)| *t == upgrade_type) {
+            Some((_, w)) => *w = weight,
+            None => self.weights.push((upgrade_type, weight)),
+        }
+    }
+
+    /// Picks an upgrade type in proportion to its weight.
+    pub fn roll(&self, rng: &mut Rng) -> Option<UpgradeType> {
+        // Summed as u64 so even the largest weights can't overflow.
+        let total: u64 = self.weights.iter().map(|(_, weight)| *weight as u64).sum();
+        if total == 0 {
+            return None;
+        }
+        let mut pick = rng.next_u64() % total;
+        for (upgrade_type, weight) in self.weights.iter() {
+            if pick < *weight as u64 {
+                return Some(*upgrade_type);
+            }
+            pick -= *weight as u64;
+        }
+        None
+    }
+
+    /// Rolls the drop for a destroyed block; blocks with a fixed upgrade
+    /// always drop it.
+    pub fn drop_for(
+        &self,
+        is_upgrade_block: bool,
+        fixed: Option<UpgradeType>,
+        rng: &mut Rng,
+    ) -> Option<UpgradeType> {
+        if fixed.is_some() {
+            return fixed;
+        }
+        let chance = match is_upgrade_block {
+            true => self.upgrade_drop_chance,
+            false => self.regular_drop_chance,
+        };
+        match rng.chance(chance) {
+            true => self.roll(rng),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::level::Level;
+
+    const ROLLS: usize = 40_000;
+
+    fn table(weights: &[(UpgradeType, u32)]) -> DropTable {
+        DropTable {
+            weights: weights.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn count(table: &DropTable, upgrade_type: UpgradeType, rng: &mut Rng) -> usize {
+        (0..ROLLS)
+            .filter(|_| table.roll(rng) == Some(upgrade_type))
+            .count()
+    }
+
+    #[test]
+    fn rolls_follow_the_weights() {
+        let table = table(&[
+            (UpgradeType::AddBall, 6),
+            (UpgradeType::Magnet, 3),
+            (UpgradeType::ExtraLife, 1),
+        ]);
+        let mut rng = Rng::new(11);
+        for (upgrade_type, weight) in table.weights.iter() {
+            let frequency = count(&table, *upgrade_type, &mut rng) as f32 / ROLLS as f32;
+            let expected = *weight as f32 / 10f32;
+            assert!(
+                (frequency - expected).abs() < 0.01,
+                "{} came out {} of the time, expected {}",
+                upgrade_type.name(),
+                frequency,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn zero_weights_never_come_out() {
+        let mut table = DropTable::default();
+        table.set_weight(UpgradeType::Death, 0);
+        table.set_weight(UpgradeType::ExtraLife, 0);
+        let mut rng = Rng::new(13);
+        assert_eq!(count(&table, UpgradeType::Death, &mut rng), 0);
+        assert_eq!(count(&table, UpgradeType::ExtraLife, &mut rng), 0);
+    }
+
+    #[test]
+    fn no_weight_means_no_drop() {
+        let table = table(&[(UpgradeType::AddBall, 0), (UpgradeType::Magnet, 0)]);
+        let mut rng = Rng::new(17);
+        assert!((0..100).all(|_| table.roll(&mut rng).is_none()));
+        assert!(DropTable {
+            weights: Vec::new(),
+            ..Default::default()
+        }
+        .roll(&mut rng)
+        .is_none());
+    }
+
+    #[test]
+    fn huge_weights_do_not_overflow() {
+        let table = table(&[
+            (UpgradeType::Magnet, u32::MAX),
+            (UpgradeType::AddBall, u32::MAX),
+            (UpgradeType::Fog, 1),
+        ]);
+        let mut rng = Rng::new(23);
+        let magnets = count(&table, UpgradeType::Magnet, &mut rng);
+        assert!((magnets as f32 / ROLLS as f32 - 0.5).abs() < 0.01);
+
+        // Straight from a level file, as reported
+        let level = Level::parse("drop_weights: magnet=4294967295\ngrid:\nU\n").unwrap();
+        assert!(level.drops.roll(&mut rng).is_some());
+    }
+
+    #[test]
+    fn drop_chances_and_fixed_drops() {
+        let table = DropTable {
+            regular_drop_chance: 0f32,
+            upgrade_drop_chance: 1f32,
+            ..Default::default()
+        };
+        let mut rng = Rng::new(19);
+        for _ in 0..100 {
+            assert!(table.drop_for(false, None, &mut rng).is_none());
+            assert!(table.drop_for(true, None, &mut rng).is_some());
+            let fixed = Some(UpgradeType::Magnet);
+            assert!(table.drop_for(false, fixed, &mut rng) == fixed);
+        }
+    }
+}
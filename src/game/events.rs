@@ -0,0 +1,73 @@
+use crate::game::block::BlockType;
+use crate::game::upgrades::UpgradeType;
+use macroquad::math::{Rect, Vec2};
+use std::collections::VecDeque;
+
+/// Something that happened during a simulation step.
+#[derive(Clone, Copy, PartialEq)]
+pub enum GameEvent {
+    BlockHit {
+        rect: Rect,
+    },
+    BlockDestroyed {
+        rect: Rect,
+        block_type: BlockType,
+        upgrade_type: Option<UpgradeType>,
+        score: i32,
+    },
+    Explosion {
+        center: Vec2,
+        radius: f32,
+    },
+    PaddleHit,
+    ProjectileFired,
+    UpgradeCollected {
+        upgrade_type: UpgradeType,
+        rect: Rect,
+    },
+    BallLost {
+        rect: Rect,
+    },
+    LifeLost,
+    LifeGained,
+    LevelCleared,
+}
+
+/// Queue of gameplay events.
+///
+/// The simulation emits events while it steps and dispatches them to its own
+/// systems (score, drops, upgrades) at the end of the step. Dispatched events
+/// are published for the frontend (audio, particles) to drain once per frame.
+pub struct EventBus {
+    pending: VecDeque<GameEvent>,
+    published: Vec<GameEvent>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            published: Vec::new(),
+        }
+    }
+
+    pub fn emit(&mut self, event: GameEvent) {
+        self.pending.push_back(event);
+    }
+
+    /// Takes the oldest pending event and publishes it.
+    pub fn next_pending(&mut self) -> Option<GameEvent> {
+        let event = self.pending.pop_front()?;
+        self.published.push(event);
+        Some(event)
+    }
+
+    pub fn drain_published(&mut self) -> std::vec::Drain<'_, GameEvent> {
+        self.published.drain(..)
+    }
+
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.published.clear();
+    }
+}
@@ -0,0 +1,84 @@
+use macroquad::math::{Rect, Vec2};
+
+/// Uniform grid over the play area used as a collision broad phase: each
+/// cell lists the blocks overlapping it, so a query only looks at blocks
+/// near the queried area.
+pub struct SpatialGrid {
+    origin: Vec2,
+    cell_size: f32,
+    cols: usize,
+    rows: usize,
+    cells: Vec<Vec<usize>>,
+}
+
+impl SpatialGrid {
+    pub fn new() -> Self {
+        Self {
+            origin: Vec2::ZERO,
+            cell_size: 1f32,
+            cols: 0,
+            rows: 0,
+            cells: Vec::new(),
+        }
+    }
+
+    /// Rebuilds the grid over `bounds` from the rects of the blocks, keyed by
+    /// their index. Rects outside the bounds are stored in the edge cells.
+    pub fn rebuild(&mut self, bounds: Rect, cell_size: f32, rects: impl Iterator<Item = Rect>) {
+        self.origin = bounds.point();
+        self.cell_size = cell_size;
+        self.cols = ((bounds.w / cell_size).ceil() as usize).max(1);
+        self.rows = ((bounds.h / cell_size).ceil() as usize).max(1);
+        self.cells.resize_with(self.cols * self.rows, Vec::new);
+        for cell in self.cells.iter_mut() {
+            cell.clear();
+        }
+        for (index, rect) in rects.enumerate() {
+            let (col_range, row_range) = self.cell_range(rect);
+            for row in row_range {
+                for col in col_range.clone() {
+                    self.cells[row * self.cols + col].push(index);
+                }
+            }
+        }
+    }
+
+    /// Fills `out` with the sorted, deduplicated indices of the blocks in
+    /// the cells overlapped by `area`.
+    pub fn query(&self, area: Rect, out: &mut Vec<usize>) {
+        out.clear();
+        if self.cells.is_empty() {
+            return;
+        }
+        let (col_range, row_range) = self.cell_range(area);
+        for row in row_range {
+            for col in col_range.clone() {
+                out.extend_from_slice(&self.cells[row * self.cols + col]);
+            }
+        }
+        out.sort_unstable();
+        out.dedup();
+    }
+
+    fn cell_range(
+        &self,
+        rect: Rect,
+    ) -> (
+        std::ops::RangeInclusive<usize>,
+        std::ops::RangeInclusive<usize>,
+    ) {
+        let cell = |value: f32, origin: f32, count: usize| {
+            (((value - origin) / self.cell_size).floor().max(0f32) as usize).min(count - 1)
+        };
+        let cols = cell(rect.left(), self.origin.x, self.cols)
+            ..=cell(rect.right(), self.origin.x, self.cols);
+        let rows = cell(rect.top(), self.origin.y, self.rows)
+            ..=cell(rect.bottom(), self.origin.y, self.rows);
+        (cols, rows)
+    }
+}
+
+/// The area covered by `rect` while it moves along `delta`.
+pub fn swept_area(rect: Rect, delta: Vec2) -> Rect {
+    rect.combine_with(rect.offset(delta))
+}
@@ -0,0 +1,33 @@
+/// The player input for one simulation step.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub struct InputSnapshot {
+    pub left: bool,
+    pub right: bool,
+    pub launch: bool,
+    /// Launch went down since the last step; held launch input doesn't
+    /// count.
+    pub launch_pressed: bool,
+    pub fire: bool,
+    /// Horizontal position the paddle should follow, when steering with the
+    /// mouse or a touch screen.
+    pub pointer_x: Option<f32>,
+}
+
+/// How the paddle follows the pointer.
+#[derive(Clone, Copy, PartialEq)]
+pub struct PointerControl {
+    /// Time in seconds the paddle takes to cover most of the distance to the
+    /// pointer; zero follows it directly.
+    pub smoothing: f32,
+    /// Top speed, relative to the keyboard speed.
+    pub max_speed: f32,
+}
+
+impl Default for PointerControl {
+    fn default() -> Self {
+        Self {
+            smoothing: 0.04,
+            max_speed: 2f32,
+        }
+    }
+}
@@ -0,0 +1,497 @@
+use crate::game::ball::BallCapPolicy;
+use crate::game::drops::DropTable;
+use crate::game::upgrades::UpgradeType;
+use std::fmt;
+
+// Most hits a block can take, the same as the `5` grid cell
+const MAX_BLOCK_LIVES: i32 = 5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Cell {
+    Empty,
+    Regular,
+    /// A regular block that takes the given number of hits.
+    MultiHit(i32),
+    Upgrade(Option<UpgradeType>),
+    Steel,
+    Explosive,
+    /// A moving block sliding between the two columns.
+    Moving(usize, usize),
+}
+
+impl Cell {
+    fn is_breakable(&self) -> bool {
+        !matches!(self, Cell::Empty | Cell::Steel)
+    }
+}
+
+pub struct Level {
+    pub name: String,
+    pub lives: i32,
+    pub block_lives: i32,
+    pub random_upgrades: usize,
+    pub drops: DropTable,
+    /// Upgrades placed on random blocks, so they drop at least once.
+    pub guaranteed_drops: Vec<UpgradeType>,
+    pub max_balls: usize,
+    pub ball_cap_policy: BallCapPolicy,
+    pub width: usize,
+    pub height: usize,
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug)]
+pub struct LevelError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl LevelError {
+    fn new(line: usize, column: usize, message: impl Into<String>) -> Self {
+        Self {
+            line,
+            column,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for LevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl std::error::Error for LevelError {}
+
+impl Level {
+    /// Parses a level file.
+    ///
+    /// The file starts with `key: value` headers followed by a `grid:` line
+    /// and one row of cells per line, without blank lines between rows. Blank
+    /// lines and lines starting with `#` are ignored in the header section.
+    ///
+    /// Cells: `.` empty, `R` regular, `1`-`5` regular taking that many hits,
+    /// `#` steel, `*` explosive, `~` moving, `U` upgrade with a random drop,
+    /// and `M`, `B`, `A`, `S`, `L`, `I` for upgrade blocks with a fixed drop
+    /// (magnet, ball multiplier, add ball, super ball, extra life, space invader).
+    /// A moving block slides over the empty cells next to it in its row.
+    ///
+    /// Drops are tuned with `drop_weights: magnet=3, extra_life=1, ...`,
+    /// `drop_chance` for regular blocks, `upgrade_drop_chance` for `U` blocks,
+    /// and comma separated `guaranteed_drops` / `forbidden_drops` lists. A
+    /// forbidden upgrade can't also be guaranteed or fixed by a grid cell.
+    /// `max_balls` caps the balls in play, and `ball_cap` picks what happens to
+    /// extra balls: `score`, `super_ball` or `ignore`.
+    pub fn parse(source: &str) -> Result<Self, LevelError> {
+        let mut name = String::from("Untitled");
+        let mut lives = 3;
+        let mut block_lives = 2;
+        let mut random_upgrades = 0;
+        let mut drops = DropTable::default();
+        let mut guaranteed_drops = Vec::new();
+        let mut forbidden_drops = Vec::new();
+        let mut max_balls = 32;
+        let mut ball_cap_policy = BallCapPolicy::Score;
+        let mut lines = source.lines().enumerate();
+
+        let mut grid_found = false;
+        let mut last_line = 0;
+        for (index, line) in lines.by_ref() {
+            let line_number = index + 1;
+            last_line = line_number;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = trimmed.split_once(':') else {
+                return Err(LevelError::new(
+                    line_number,
+                    column_of(line, trimmed),
+                    format!("expected `key: value`, found `{}`", trimmed),
+                ));
+            };
+            let key = key.trim();
+            let value = value.trim();
+            let value_column = column_of(line, value);
+            match key {
+                "name" => name = value.to_string(),
+                "lives" => lives = parse_number(value, 1, line_number, value_column)?,
+                "block_lives" => {
+                    block_lives = parse_number(value, 1, line_number, value_column)?;
+                    if block_lives > MAX_BLOCK_LIVES {
+                        return Err(LevelError::new(
+                            line_number,
+                            value_column,
+                            format!("value must be at most {}", MAX_BLOCK_LIVES),
+                        ));
+                    }
+                }
+                "random_upgrades" => {
+                    random_upgrades = parse_number(value, 0, line_number, value_column)?
+                }
+                "drop_weights" => {
+                    for (item, column) in split_list(line, value) {
+                        let Some((upgrade, weight)) = item.split_once('=') else {
+                            return Err(LevelError::new(
+                                line_number,
+                                column,
+                                format!("expected `upgrade=weight`, found `{}`", item),
+                            ));
+                        };
+                        let upgrade = upgrade.trim();
+                        let weight = weight.trim();
+                        let upgrade_type = parse_upgrade(upgrade, line_number, column)?;
+                        let weight = parse_number(weight, 0, line_number, column_of(line, weight))?;
+                        drops.set_weight(upgrade_type, weight);
+                    }
+                }
+                "drop_chance" => {
+                    drops.regular_drop_chance = parse_probability(value, line_number, value_column)?
+                }
+                "upgrade_drop_chance" => {
+                    drops.upgrade_drop_chance = parse_probability(value, line_number, value_column)?
+                }
+                "guaranteed_drops" => {
+                    for (item, column) in split_list(line, value) {
+                        let upgrade_type = parse_upgrade(item, line_number, column)?;
+                        if forbidden_drops.contains(&upgrade_type) {
+                            return Err(both_guaranteed_and_forbidden(item, line_number, column));
+                        }
+                        guaranteed_drops.push(upgrade_type);
+                    }
+                }
+                "forbidden_drops" => {
+                    for (item, column) in split_list(line, value) {
+                        let upgrade_type = parse_upgrade(item, line_number, column)?;
+                        if guaranteed_drops.contains(&upgrade_type) {
+                            return Err(both_guaranteed_and_forbidden(item, line_number, column));
+                        }
+                        forbidden_drops.push(upgrade_type);
+                    }
+                }
+                "max_balls" => max_balls = parse_number(value, 1, line_number, value_column)?,
+                "ball_cap" => {
+                    ball_cap_policy = BallCapPolicy::from_name(value).ok_or_else(|| {
+                        LevelError::new(
+                            line_number,
+                            value_column,
+                            format!(
+                                "expected `score`, `super_ball` or `ignore`, found `{}`",
+                                value
+                            ),
+                        )
+                    })?
+                }
+                "grid" => {
+                    if !value.is_empty() {
+                        return Err(LevelError::new(
+                            line_number,
+                            value_column,
+                            "the grid starts on the line after `grid:`",
+                        ));
+                    }
+                    grid_found = true;
+                    break;
+                }
+                _ => {
+                    return Err(LevelError::new(
+                        line_number,
+                        column_of(line, key),
+                        format!("unknown key `{}`", key),
+                    ))
+                }
+            }
+        }
+
+        if !grid_found {
+            return Err(LevelError::new(
+                last_line.max(1),
+                1,
+                "missing `grid:` section",
+            ));
+        }
+
+        let mut width = 0;
+        let mut cells = Vec::new();
+        let mut height = 0;
+        let mut blank_line = None;
+        for (index, line) in lines {
+            let line_number = index + 1;
+            last_line = line_number;
+            let row = line.trim_end();
+            if row.is_empty() {
+                blank_line = blank_line.or(Some(line_number));
+                continue;
+            }
+            // Blank lines are only allowed after the last row.
+            if let (Some(blank_line), true) = (blank_line, height > 0) {
+                return Err(LevelError::new(
+                    blank_line,
+                    1,
+                    "blank line inside the grid, use `.` for empty cells",
+                ));
+            }
+            blank_line = None;
+            let row_width = row.chars().count();
+            if height == 0 {
+                width = row_width;
+            } else if row_width != width {
+                return Err(LevelError::new(
+                    line_number,
+                    row_width.min(width) + 1,
+                    format!("row has {} cells, expected {}", row_width, width),
+                ));
+            }
+            for (column, c) in row.chars().enumerate() {
+                let cell = match c {
+                    '.' => Cell::Empty,
+                    'R' => Cell::Regular,
+                    '1'..='5' => Cell::MultiHit(c as i32 - '0' as i32),
+                    '#' => Cell::Steel,
+                    '*' => Cell::Explosive,
+                    '~' => Cell::Moving(column, column),
+                    'U' => Cell::Upgrade(None),
+                    'M' => Cell::Upgrade(Some(UpgradeType::Magnet)),
+                    'B' => Cell::Upgrade(Some(UpgradeType::BallMultiplier)),
+                    'A' => Cell::Upgrade(Some(UpgradeType::AddBall)),
+                    'S' => Cell::Upgrade(Some(UpgradeType::SuperBall)),
+                    'L' => Cell::Upgrade(Some(UpgradeType::ExtraLife)),
+                    'I' => Cell::Upgrade(Some(UpgradeType::SpaceInvader)),
+                    _ => {
+                        return Err(LevelError::new(
+                            line_number,
+                            column + 1,
+                            format!("unknown cell `{}`", c),
+                        ))
+                    }
+                };
+                if let Cell::Upgrade(Some(upgrade_type)) = cell {
+                    if forbidden_drops.contains(&upgrade_type) {
+                        return Err(LevelError::new(
+                            line_number,
+                            column + 1,
+                            format!(
+                                "`{}` drops `{}`, which is forbidden",
+                                c,
+                                upgrade_type.name()
+                            ),
+                        ));
+                    }
+                }
+                cells.push(cell);
+            }
+            height += 1;
+        }
+
+        if height == 0 {
+            return Err(LevelError::new(last_line + 1, 1, "the grid is empty"));
+        }
+        if !cells.iter().any(Cell::is_breakable) {
+            return Err(LevelError::new(
+                last_line + 1,
+                1,
+                "the grid has no breakable blocks",
+            ));
+        }
+        for row in cells.chunks_mut(width) {
+            assign_moving_paths(row);
+        }
+
+        for upgrade_type in forbidden_drops {
+            drops.set_weight(upgrade_type, 0);
+        }
+
+        Ok(Self {
+            name,
+            lives,
+            block_lives,
+            random_upgrades,
+            drops,
+            guaranteed_drops,
+            max_balls,
+            ball_cap_policy,
+            width,
+            height,
+            cells,
+        })
+    }
+}
+
+/// Lets each moving block slide over the empty cells around it, without
+/// sharing any of them with the moving block before it.
+fn assign_moving_paths(row: &mut [Cell]) {
+    let mut free_from = 0;
+    for column in 0..row.len() {
+        if let Cell::Moving(..) = row[column] {
+            let mut left = column;
+            while left > free_from && row[left - 1] == Cell::Empty {
+                left -= 1;
+            }
+            let mut right = column;
+            while right + 1 < row.len() && row[right + 1] == Cell::Empty {
+                right += 1;
+            }
+            row[column] = Cell::Moving(left, right);
+            free_from = right + 1;
+        } else if row[column] != Cell::Empty {
+            free_from = column + 1;
+        }
+    }
+}
+
+fn column_of(line: &str, part: &str) -> usize {
+    part.as_ptr() as usize - line.as_ptr() as usize + 1
+}
+
+/// Splits a comma separated value into trimmed items with their columns.
+fn split_list<'a>(line: &'a str, value: &'a str) -> Vec<(&'a str, usize)> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| (item, column_of(line, item)))
+        .collect()
+}
+
+fn parse_upgrade(name: &str, line: usize, column: usize) -> Result<UpgradeType, LevelError> {
+    UpgradeType::from_name(name)
+        .ok_or_else(|| LevelError::new(line, column, format!("unknown upgrade `{}`", name)))
+}
+
+fn both_guaranteed_and_forbidden(name: &str, line: usize, column: usize) -> LevelError {
+    LevelError::new(
+        line,
+        column,
+        format!("`{}` is both guaranteed and forbidden", name),
+    )
+}
+
+fn parse_probability(value: &str, line: usize, column: usize) -> Result<f32, LevelError> {
+    let probability: f32 = parse_number(value, 0f32, line, column)?;
+    if probability > 1f32 {
+        return Err(LevelError::new(line, column, "value must be at most 1"));
+    }
+    Ok(probability)
+}
+
+fn parse_number<T>(value: &str, min: T, line: usize, column: usize) -> Result<T, LevelError>
+where
+    T: std::str::FromStr + PartialOrd + fmt::Display,
+{
+    match value.parse::<T>() {
+        Ok(number) if number >= min => Ok(number),
+        Ok(_) => Err(LevelError::new(
+            line,
+            column,
+            format!("value must be at least {}", min),
+        )),
+        Err(_) => Err(LevelError::new(
+            line,
+            column,
+            format!("expected a number, found `{}`", value),
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(source: &str) -> (usize, usize, String) {
+        match Level::parse(source) {
+            Ok(_) => panic!("the level should not parse"),
+            Err(error) => (error.line, error.column, error.message),
+        }
+    }
+
+    #[test]
+    fn unknown_cell() {
+        let (line, column, message) = error("name: Test\ngrid:\nRRR\nR?R\n");
+        assert_eq!((line, column), (4, 2));
+        assert_eq!(message, "unknown cell `?`");
+    }
+
+    #[test]
+    fn ragged_row() {
+        let (line, column, message) = error("grid:\nRRRR\nRR\n");
+        assert_eq!((line, column), (3, 3));
+        assert_eq!(message, "row has 2 cells, expected 4");
+    }
+
+    #[test]
+    fn missing_grid() {
+        let (line, column, message) = error("name: Test\nlives: 3\n");
+        assert_eq!((line, column), (2, 1));
+        assert_eq!(message, "missing `grid:` section");
+    }
+
+    #[test]
+    fn bad_number() {
+        let (line, column, message) = error("name: Test\nlives:  three\ngrid:\nR\n");
+        assert_eq!((line, column), (2, 9));
+        assert_eq!(message, "expected a number, found `three`");
+
+        let (line, column, _) = error("drop_weights: magnet=3, fog=-1\ngrid:\nR\n");
+        assert_eq!((line, column), (1, 29));
+    }
+
+    #[test]
+    fn block_lives_is_capped_like_grid_cells() {
+        let (line, column, message) = error("block_lives: 2147483647\ngrid:\n~.\n");
+        assert_eq!((line, column), (1, 14));
+        assert_eq!(message, "value must be at most 5");
+        assert_eq!(
+            Level::parse("block_lives: 5\ngrid:\nR\n")
+                .unwrap()
+                .block_lives,
+            5
+        );
+    }
+
+    #[test]
+    fn empty_grid() {
+        let (line, column, message) = error("name: Test\ngrid:\n\n");
+        assert_eq!((line, column), (4, 1));
+        assert_eq!(message, "the grid is empty");
+    }
+
+    #[test]
+    fn blank_line_inside_the_grid() {
+        let (line, column, _) = error("grid:\nR\n\nR\n");
+        assert_eq!((line, column), (3, 1));
+        // Trailing blank lines are fine.
+        let level = Level::parse("grid:\nR\nR\n\n\n").unwrap();
+        assert_eq!((level.width, level.height), (1, 2));
+    }
+
+    #[test]
+    fn forbidden_drops_are_zero_weight() {
+        let level = Level::parse("forbidden_drops: death, fog\ngrid:\nRU\n").unwrap();
+        for (upgrade_type, weight) in level.drops.weights.iter() {
+            if matches!(upgrade_type, UpgradeType::Death | UpgradeType::Fog) {
+                assert_eq!(*weight, 0);
+            }
+        }
+    }
+
+    #[test]
+    fn guaranteed_drop_cannot_be_forbidden() {
+        let (line, column, message) =
+            error("guaranteed_drops: magnet\nforbidden_drops: fog, magnet\ngrid:\nR\n");
+        assert_eq!((line, column), (2, 23));
+        assert_eq!(message, "`magnet` is both guaranteed and forbidden");
+
+        let (line, column, _) =
+            error("forbidden_drops: magnet\nguaranteed_drops: add_ball, magnet\ngrid:\nR\n");
+        assert_eq!((line, column), (2, 29));
+    }
+
+    #[test]
+    fn grid_cell_cannot_fix_a_forbidden_drop() {
+        let (line, column, message) = error("forbidden_drops: extra_life\ngrid:\nRRR\nRLR\n");
+        assert_eq!((line, column), (4, 2));
+        assert_eq!(message, "`L` drops `extra_life`, which is forbidden");
+    }
+}
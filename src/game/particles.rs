@@ -0,0 +1,76 @@
+use crate::game::rng::Rng;
+use macroquad::prelude::*;
+
+const GRAVITY: f32 = 600f32;
+const PARTICLE_LIFETIME: f32 = 0.6;
+
+struct Particle {
+    pos: Vec2,
+    vel: Vec2,
+    size: f32,
+    color: Color,
+    remaining: f32,
+}
+
+/// Short-lived debris used for visual feedback. Particles have their own
+/// random generator so they never affect the simulation.
+pub struct Particles {
+    particles: Vec<Particle>,
+    rng: Rng,
+    /// Multiplier for the number of particles in each burst.
+    pub density: f32,
+}
+
+impl Particles {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            particles: Vec::new(),
+            rng: Rng::new(seed),
+            density: 1f32,
+        }
+    }
+
+    /// Throws `count` particles out of `center` in random directions.
+    pub fn burst(&mut self, center: Vec2, color: Color, count: usize, speed: f32, size: f32) {
+        let count = (count as f32 * self.density).round() as usize;
+        for _ in 0..count {
+            let angle = self.rng.gen_range_f32(0f32, std::f32::consts::TAU);
+            let speed = speed * self.rng.gen_range_f32(0.3f32, 1f32);
+            self.particles.push(Particle {
+                pos: center,
+                vel: Vec2::from_angle(angle) * speed,
+                size,
+                color,
+                remaining: PARTICLE_LIFETIME * self.rng.gen_range_f32(0.5f32, 1f32),
+            });
+        }
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        for particle in self.particles.iter_mut() {
+            particle.vel.y += GRAVITY * dt;
+            particle.pos += particle.vel * dt;
+            particle.remaining -= dt;
+        }
+        self.particles.retain(|particle| particle.remaining > 0f32);
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+    }
+
+    pub fn draw(&self) {
+        for particle in self.particles.iter() {
+            let mut color = particle.color;
+            color.a = (particle.remaining / PARTICLE_LIFETIME).clamp(0f32, 1f32);
+            let half = particle.size * 0.5f32;
+            draw_rectangle(
+                particle.pos.x - half,
+                particle.pos.y - half,
+                particle.size,
+                particle.size,
+                color,
+            );
+        }
+    }
+}
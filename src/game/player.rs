@@ -1,45 +1,120 @@
+use crate::game::input::{InputSnapshot, PointerControl};
 use crate::game::scale::Scale;
+use crate::game::timestep::interpolate;
 use macroquad::prelude::*;
 
+// Paddle widths per second, relative to the base width, while resizing
+const RESIZE_SPEED: f32 = 2f32;
+
 pub struct Player {
     pub rect: Rect,
+    pub prev_rect: Rect,
+    pub velocity: f32,
+    pub base_width: f32,
+    target_width: f32,
     speed: f32,
     relative_pos_y: f32,
 }
 
 impl Player {
-    pub fn new(size: Vec2, speed: f32, relative_pos_y: f32, scale: Scale) -> Self {
+    pub fn new(size: Vec2, speed: f32, relative_pos_y: f32, scale: Scale, bounds: Rect) -> Self {
+        let rect = Rect::new(
+            bounds.w * 0.5f32 - size.x * 0.5f32,
+            bounds.bottom() - relative_pos_y,
+            size.x * scale.total_scale,
+            size.y * scale.total_scale,
+        );
         Self {
-            rect: Rect::new(
-                screen_width() * 0.5f32 - size.x * 0.5f32,
-                screen_height() - relative_pos_y,
-                size.x * scale.total_scale,
-                size.y * scale.total_scale,
-            ),
+            rect,
+            prev_rect: rect,
+            velocity: 0f32,
+            base_width: rect.w,
+            target_width: rect.w,
             speed: speed * scale.total_scale,
             relative_pos_y,
         }
     }
 
-    pub fn update(&mut self, dt: f32) {
-        let x_move = match (is_key_down(KeyCode::Left), is_key_down(KeyCode::Right)) {
-            (true, false) => -1f32,
-            (false, true) => 1f32,
-            _ => 0f32,
-        };
-        self.rect.x += x_move * dt * self.speed;
+    /// Starts animating the paddle towards `width`.
+    pub fn resize_to(&mut self, width: f32) {
+        self.target_width = width;
+    }
+
+    /// Snaps the paddle back to its base width.
+    pub fn reset_width(&mut self) {
+        self.target_width = self.base_width;
+        self.set_width(self.base_width);
+        self.prev_rect = self.rect;
+    }
+
+    /// Puts the paddle back where a saved run left it.
+    pub fn restore(&mut self, rect: Rect, base_width: f32) {
+        self.rect = rect;
+        self.prev_rect = rect;
+        self.base_width = base_width;
+        self.target_width = rect.w;
+    }
+
+    /// Resizes the paddle around its center.
+    fn set_width(&mut self, width: f32) {
+        self.rect.x += (self.rect.w - width) * 0.5f32;
+        self.rect.w = width;
+    }
+
+    pub fn update(
+        &mut self,
+        dt: f32,
+        input: &InputSnapshot,
+        bounds: Rect,
+        pointer_control: PointerControl,
+    ) {
+        self.prev_rect = self.rect;
+        let max_change = RESIZE_SPEED * self.base_width * dt;
+        let width = self.rect.w + (self.target_width - self.rect.w).clamp(-max_change, max_change);
+        self.set_width(width.min(bounds.w));
+
+        match input.pointer_x {
+            // Ease towards the pointer without going over the top speed.
+            Some(target) => {
+                let offset = target - self.rect.center().x;
+                let fraction = match pointer_control.smoothing > 0f32 {
+                    true => 1f32 - (-dt / pointer_control.smoothing).exp(),
+                    false => 1f32,
+                };
+                let max_move = pointer_control.max_speed * self.speed * dt;
+                self.rect.x += (offset * fraction).clamp(-max_move, max_move);
+            }
+            None => {
+                let x_move = match (input.left, input.right) {
+                    (true, false) => -1f32,
+                    (false, true) => 1f32,
+                    _ => 0f32,
+                };
+                self.rect.x += x_move * dt * self.speed;
+            }
+        }
 
-        if self.rect.x <= 0f32 {
-            self.rect.x = 0f32;
+        if self.rect.x <= bounds.left() {
+            self.rect.x = bounds.left();
         }
-        if self.rect.x + self.rect.w >= screen_width() {
-            self.rect.x = screen_width() - self.rect.w;
+        if self.rect.x + self.rect.w >= bounds.right() {
+            self.rect.x = bounds.right() - self.rect.w;
         }
 
-        self.rect.y = screen_height() - self.relative_pos_y;
+        self.rect.y = bounds.bottom() - self.relative_pos_y;
+        self.velocity = (self.rect.x - self.prev_rect.x) / dt;
     }
 
-    pub fn draw(&self) {
-        draw_rectangle(self.rect.x, self.rect.y, self.rect.w, self.rect.h, BLUE);
+    pub fn draw(&self, alpha: f32) {
+        let rect = interpolate(self.prev_rect, self.rect, alpha);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, BLUE);
+    }
+
+    pub fn draw_cannons(&self, alpha: f32) {
+        let rect = interpolate(self.prev_rect, self.rect, alpha);
+        let size = vec2(rect.h * 0.4f32, rect.h * 0.6f32);
+        for x in [rect.left(), rect.right() - size.x] {
+            draw_rectangle(x, rect.y - size.y, size.x, size.y, RED);
+        }
     }
 }
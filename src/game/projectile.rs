@@ -0,0 +1,28 @@
+use crate::game::timestep::interpolate;
+use macroquad::prelude::*;
+
+pub struct Projectile {
+    pub rect: Rect,
+    pub prev_rect: Rect,
+    speed: f32,
+}
+
+impl Projectile {
+    pub fn new(position: Vec2, size: Vec2, speed: f32) -> Self {
+        let rect = Rect::new(position.x, position.y, size.x, size.y);
+        Self {
+            rect,
+            prev_rect: rect,
+            speed,
+        }
+    }
+
+    pub fn delta(&self, dt: f32) -> Vec2 {
+        vec2(0f32, -self.speed * dt)
+    }
+
+    pub fn draw(&self, alpha: f32) {
+        let rect = interpolate(self.prev_rect, self.rect, alpha);
+        draw_rectangle(rect.x, rect.y, rect.w, rect.h, RED);
+    }
+}
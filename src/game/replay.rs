@@ -0,0 +1,452 @@
+use crate::game::input::{InputSnapshot, PointerControl};
+use crate::game::level::Level;
+use crate::game::snapshot::Snapshot;
+use crate::game::world::{StepOutcome, World};
+use macroquad::math::Rect;
+
+const MAGIC: &str = "breakdown-replay";
+const VERSION: u32 = 2;
+const TOKENS_PER_LINE: usize = 16;
+
+/// Something the game did to the simulation while recording.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Command {
+    /// One simulation step.
+    Step(InputSnapshot),
+    /// One step while waiting for a new ball, where only the paddle moves.
+    MovePlayer(InputSnapshot),
+    /// Puts a new ball next to the paddle.
+    Launch,
+    /// Moves on to the next level with the given bounds.
+    NextLevel(Rect),
+    /// Starts the current level over with the given bounds.
+    RestartLevel(Rect),
+    AutoFire(bool),
+    PointerControl(PointerControl),
+}
+
+impl Command {
+    fn to_text(self) -> String {
+        match self {
+            Command::Step(input) => format!("s{}", input_text(input)),
+            Command::MovePlayer(input) => format!("m{}", input_text(input)),
+            Command::Launch => String::from("l"),
+            Command::NextLevel(bounds) => format!("n{}", bounds_text(bounds)),
+            Command::RestartLevel(bounds) => format!("r{}", bounds_text(bounds)),
+            Command::AutoFire(on) => format!("a{}", on as u8),
+            Command::PointerControl(control) => {
+                format!("p{},{}", control.smoothing, control.max_speed)
+            }
+        }
+    }
+
+    fn parse(token: &str) -> Option<Self> {
+        let mut chars = token.chars();
+        let kind = chars.next()?;
+        let value = chars.as_str();
+        match kind {
+            's' => Some(Command::Step(parse_input(value)?)),
+            'm' => Some(Command::MovePlayer(parse_input(value)?)),
+            'l' if value.is_empty() => Some(Command::Launch),
+            'n' => Some(Command::NextLevel(parse_bounds(value)?)),
+            'r' => Some(Command::RestartLevel(parse_bounds(value)?)),
+            'a' => match value {
+                "0" => Some(Command::AutoFire(false)),
+                "1" => Some(Command::AutoFire(true)),
+                _ => None,
+            },
+            'p' => {
+                let (smoothing, max_speed) = value.split_once(',')?;
+                Some(Command::PointerControl(PointerControl {
+                    smoothing: smoothing.parse().ok()?,
+                    max_speed: max_speed.parse().ok()?,
+                }))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The buttons as hex bits, followed by `@x` when steering with the
+/// pointer.
+fn input_text(input: InputSnapshot) -> String {
+    let bits = input.left as u8
+        | (input.right as u8) << 1
+        | (input.launch as u8) << 2
+        | (input.fire as u8) << 3
+        | (input.launch_pressed as u8) << 4;
+    match input.pointer_x {
+        Some(x) => format!("{:x}@{}", bits, x),
+        None => format!("{:x}", bits),
+    }
+}
+
+fn parse_input(value: &str) -> Option<InputSnapshot> {
+    let (bits, pointer_x) = match value.split_once('@') {
+        Some((bits, x)) => (bits, Some(x.parse().ok()?)),
+        None => (value, None),
+    };
+    let bits = u8::from_str_radix(bits, 16)
+        .ok()
+        .filter(|bits| *bits < 32)?;
+    Some(InputSnapshot {
+        left: bits & 1 != 0,
+        right: bits & 2 != 0,
+        launch: bits & 4 != 0,
+        fire: bits & 8 != 0,
+        launch_pressed: bits & 16 != 0,
+        pointer_x,
+    })
+}
+
+fn bounds_text(bounds: Rect) -> String {
+    format!("{},{},{},{}", bounds.x, bounds.y, bounds.w, bounds.h)
+}
+
+fn parse_bounds(value: &str) -> Option<Rect> {
+    let values: Vec<f32> = value
+        .split(',')
+        .map(|value| value.parse().ok())
+        .collect::<Option<_>>()?;
+    match values[..] {
+        [x, y, w, h] => Some(Rect::new(x, y, w, h)),
+        _ => None,
+    }
+}
+
+/// A recorded run: where it started and everything the player did, so it
+/// can be played back exactly.
+///
+/// Written as text with a run-length encoded command list, e.g. `120*s0`
+/// for 120 steps without input.
+pub struct Replay {
+    pub seed: u64,
+    pub level_index: usize,
+    pub bounds: Rect,
+    pub auto_fire: bool,
+    /// Simulation ticks per second while recording; playback must use the
+    /// same rate.
+    pub tick_rate: f32,
+    /// Commands with their repeat counts.
+    commands: Vec<(u32, Command)>,
+    /// Final score and state hash, filled in when the recording ends.
+    pub result: Option<(i32, u64)>,
+}
+
+impl Replay {
+    pub fn new(
+        seed: u64,
+        level_index: usize,
+        bounds: Rect,
+        auto_fire: bool,
+        tick_rate: f32,
+    ) -> Self {
+        Self {
+            seed,
+            level_index,
+            bounds,
+            auto_fire,
+            tick_rate,
+            commands: Vec::new(),
+            result: None,
+        }
+    }
+
+    pub fn record(&mut self, command: Command) {
+        match self.commands.last_mut() {
+            Some((count, last)) if *last == command => *count += 1,
+            _ => self.commands.push((1, command)),
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("{} {}", MAGIC, VERSION),
+            format!("seed {}", self.seed),
+            format!("level {}", self.level_index),
+            format!("bounds {}", bounds_text(self.bounds)),
+            format!("auto_fire {}", self.auto_fire),
+            format!("tick_rate {}", self.tick_rate),
+        ];
+        if let Some((score, hash)) = self.result {
+            lines.push(format!("score {}", score));
+            lines.push(format!("hash {:016x}", hash));
+        }
+        lines.push(String::from("commands"));
+        let tokens: Vec<String> = self
+            .commands
+            .iter()
+            .map(|(count, command)| match count {
+                1 => command.to_text(),
+                _ => format!("{}*{}", count, command.to_text()),
+            })
+            .collect();
+        for chunk in tokens.chunks(TOKENS_PER_LINE) {
+            lines.push(chunk.join(" "));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines().enumerate();
+        let header = lines.next().map_or("", |(_, line)| line.trim());
+        if header != format!("{} {}", MAGIC, VERSION) {
+            return Err(format!(
+                "unsupported replay format `{}`, expected `{} {}`",
+                header, MAGIC, VERSION
+            ));
+        }
+        let mut seed = None;
+        let mut level_index = None;
+        let mut bounds = None;
+        let mut auto_fire = None;
+        let mut tick_rate = None;
+        let mut score = None;
+        let mut hash = None;
+        for (index, line) in lines.by_ref() {
+            let invalid = || format!("{}: invalid line `{}`", index + 1, line);
+            let (key, value) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+            match key {
+                "" => {}
+                "seed" => seed = Some(value.parse().map_err(|_| invalid())?),
+                "level" => level_index = Some(value.parse().map_err(|_| invalid())?),
+                "bounds" => bounds = Some(parse_bounds(value).ok_or_else(invalid)?),
+                "auto_fire" => auto_fire = Some(value.parse().map_err(|_| invalid())?),
+                "tick_rate" => {
+                    let rate = value.parse().ok().filter(|rate: &f32| *rate > 0f32);
+                    tick_rate = Some(rate.ok_or_else(invalid)?)
+                }
+                "score" => score = Some(value.parse().map_err(|_| invalid())?),
+                "hash" => hash = Some(u64::from_str_radix(value, 16).map_err(|_| invalid())?),
+                "commands" => break,
+                _ => return Err(invalid()),
+            }
+        }
+
+        let mut commands = Vec::new();
+        for (index, line) in lines {
+            for token in line.split_whitespace() {
+                let invalid = || format!("{}: invalid command `{}`", index + 1, token);
+                let (count, command) = match token.split_once('*') {
+                    Some((count, command)) => {
+                        let count = count.parse().ok().filter(|count| *count > 0);
+                        (count.ok_or_else(invalid)?, command)
+                    }
+                    None => (1, token),
+                };
+                let command = Command::parse(command).ok_or_else(invalid)?;
+                commands.push((count, command));
+            }
+        }
+
+        let missing = |key: &str| format!("missing `{}`", key);
+        Ok(Self {
+            seed: seed.ok_or_else(|| missing("seed"))?,
+            level_index: level_index.ok_or_else(|| missing("level"))?,
+            bounds: bounds.ok_or_else(|| missing("bounds"))?,
+            auto_fire: auto_fire.ok_or_else(|| missing("auto_fire"))?,
+            tick_rate: tick_rate.ok_or_else(|| missing("tick_rate"))?,
+            commands,
+            result: score.zip(hash),
+        })
+    }
+}
+
+/// Hash of the whole simulation state (FNV-1a of its snapshot), to check
+/// that a replay ended exactly where the recording did.
+pub fn state_hash(world: &World, level_index: usize, level: &Level, checkpoint: (i32, i32)) -> u64 {
+    let text = Snapshot::capture(world, level_index, &level.name, checkpoint).to_text();
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+/// Feeds a replay back into a world one tick at a time.
+pub struct Playback {
+    commands: Vec<(u32, Command)>,
+    position: usize,
+    /// How many times the current command has run.
+    repeated: u32,
+    pub level_index: usize,
+    /// Score and lives when the current level started.
+    pub checkpoint: (i32, i32),
+}
+
+impl Playback {
+    /// Puts `world` back at the start of the recorded run.
+    pub fn start(replay: &Replay, world: &mut World, levels: &[Level]) -> Result<Self, String> {
+        let level = levels.get(replay.level_index).ok_or_else(|| {
+            format!(
+                "the replay starts on missing level {}",
+                replay.level_index + 1
+            )
+        })?;
+        world.resize(replay.bounds);
+        world.reset(level, replay.seed);
+        world.auto_fire = replay.auto_fire;
+        Ok(Self {
+            commands: replay.commands.clone(),
+            position: 0,
+            repeated: 0,
+            level_index: replay.level_index,
+            checkpoint: (world.score, world.lives),
+        })
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.commands.len()
+    }
+
+    fn next_command(&mut self) -> Option<Command> {
+        let (count, command) = *self.commands.get(self.position)?;
+        self.repeated += 1;
+        if self.repeated >= count {
+            self.position += 1;
+            self.repeated = 0;
+        }
+        Some(command)
+    }
+
+    /// Runs the commands up to and including the next simulation step and
+    /// returns its outcome, or `None` once the replay is over.
+    pub fn tick(&mut self, world: &mut World, levels: &[Level], dt: f32) -> Option<StepOutcome> {
+        loop {
+            match self.next_command()? {
+                Command::Step(input) => return Some(world.step(&input, dt)),
+                Command::MovePlayer(input) => {
+                    world.update_player(&input, dt);
+                    return Some(StepOutcome::Playing);
+                }
+                Command::Launch => world.spawn_ball_next_to_player(),
+                Command::NextLevel(bounds) => {
+                    let Some(level) = levels.get(self.level_index + 1) else {
+                        self.position = self.commands.len();
+                        return None;
+                    };
+                    self.level_index += 1;
+                    world.resize(bounds);
+                    world.load_level(level);
+                    self.checkpoint = (world.score, world.lives);
+                }
+                Command::RestartLevel(bounds) => {
+                    let (score, lives) = self.checkpoint;
+                    world.resize(bounds);
+                    world.restart_level(&levels[self.level_index], score, lives);
+                }
+                Command::AutoFire(on) => world.auto_fire = on,
+                Command::PointerControl(control) => world.pointer_control = control,
+            }
+        }
+    }
+}
+
+/// Plays `replay` without a window and checks that it ends with the recorded
+/// score and state hash. Returns the final score and hash.
+pub fn verify(
+    replay: &Replay,
+    world: &mut World,
+    levels: &[Level],
+    dt: f32,
+) -> Result<(i32, u64), String> {
+    let mut playback = Playback::start(replay, world, levels)?;
+    while playback.tick(world, levels, dt).is_some() {
+        world.events.drain_published().for_each(drop);
+    }
+    let level = &levels[playback.level_index];
+    let hash = state_hash(world, playback.level_index, level, playback.checkpoint);
+    match replay.result {
+        Some((score, expected)) if (score, expected) == (world.score, hash) => Ok((score, hash)),
+        Some((score, expected)) => Err(format!(
+            "replay diverged: expected score {} and hash {:016x}, got score {} and hash {:016x}",
+            score, expected, world.score, hash
+        )),
+        None => Err(String::from("the replay has no recorded result")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::scale::Scale;
+
+    const DT: f32 = 1f32 / 120f32;
+
+    fn test_setup() -> (Vec<Level>, World) {
+        let levels =
+            vec![Level::parse("lives: 3\nblock_lives: 1\ngrid:\nRRRRRRRR\nRRRRRRRR\n").unwrap()];
+        let bounds = Rect::new(0f32, 0f32, 800f32, 600f32);
+        let world = World::new(bounds, Scale::for_width(0.8, 800f32, 800f32), &levels[0], 1);
+        (levels, world)
+    }
+
+    /// Plays a short run, recording what it does the way the game does.
+    fn record_run(levels: &[Level], world: &mut World) -> Replay {
+        let mut replay = Replay::new(21, 0, world.bounds, false, 120f32);
+        world.reset(&levels[0], replay.seed);
+        for tick in 0..3_000 {
+            let input = InputSnapshot {
+                left: tick % 400 < 150,
+                right: tick % 400 >= 250,
+                launch: tick % 90 == 0,
+                launch_pressed: tick % 90 == 0,
+                ..Default::default()
+            };
+            let command = match world.balls.is_empty() {
+                true => Command::MovePlayer(input),
+                false => Command::Step(input),
+            };
+            replay.record(command);
+            let outcome = match command {
+                Command::MovePlayer(input) => {
+                    world.update_player(&input, DT);
+                    StepOutcome::Playing
+                }
+                _ => world.step(&input, DT),
+            };
+            if outcome == StepOutcome::GameOver {
+                break;
+            }
+            if world.balls.is_empty() && input.launch {
+                replay.record(Command::Launch);
+                world.spawn_ball_next_to_player();
+            }
+            world.events.drain_published().for_each(drop);
+        }
+        let hash = state_hash(world, 0, &levels[0], (0, levels[0].lives));
+        replay.result = Some((world.score, hash));
+        replay
+    }
+
+    #[test]
+    fn recorded_run_verifies_after_a_text_round_trip() {
+        let (levels, mut world) = test_setup();
+        let recorded = record_run(&levels, &mut world);
+        let text = recorded.to_text();
+        let parsed = Replay::parse(&text).unwrap();
+        assert_eq!(parsed.to_text(), text);
+
+        let (_, mut fresh) = test_setup();
+        let (score, _) = verify(&parsed, &mut fresh, &levels, DT).unwrap();
+        assert_eq!(score, world.score);
+    }
+
+    #[test]
+    fn tampered_replay_fails_verification() {
+        let (levels, mut world) = test_setup();
+        let mut replay = record_run(&levels, &mut world);
+        replay
+            .commands
+            .insert(0, (1, Command::Step(InputSnapshot::default())));
+        let (_, mut fresh) = test_setup();
+        assert!(verify(&replay, &mut fresh, &levels, DT).is_err());
+    }
+
+    #[test]
+    fn damaged_replays_are_rejected() {
+        let (levels, mut world) = test_setup();
+        let text = record_run(&levels, &mut world).to_text();
+        assert!(Replay::parse(&text.replace("breakdown-replay 2", "breakdown-replay 1")).is_err());
+        assert!(Replay::parse(&text.replace("seed 21\n", "")).is_err());
+        assert!(Replay::parse(&(text + "0*s0\n")).is_err());
+    }
+}
@@ -10,7 +10,11 @@ pub struct Scale {
 
 impl Scale {
     pub fn new(scale: f32, screen_scale_factor: f32) -> Self {
-        let screen_scale = screen_width() / screen_scale_factor;
+        Self::for_width(scale, screen_scale_factor, screen_width())
+    }
+
+    pub fn for_width(scale: f32, screen_scale_factor: f32, width: f32) -> Self {
+        let screen_scale = width / screen_scale_factor;
         Self {
             scale,
             screen_scale_factor,
@@ -19,8 +23,8 @@ impl Scale {
         }
     }
 
-    pub fn update(&mut self) {
-        self.screen_scale = screen_width() / self.screen_scale_factor;
+    pub fn update_for_width(&mut self, width: f32) {
+        self.screen_scale = width / self.screen_scale_factor;
         self.total_scale = self.scale * self.screen_scale;
     }
 }
@@ -0,0 +1,506 @@
+use crate::game::ball::Ball;
+use crate::game::block::{Block, BlockType};
+use crate::game::level::Level;
+use crate::game::projectile::Projectile;
+use crate::game::rng::Rng;
+use crate::game::upgrades::{ActiveEffect, UpgradeCoin, UpgradeType, Upgrades};
+use crate::game::world::{World, PROJECTILE_SPEED};
+use macroquad::math::{vec2, Rect};
+use std::fmt;
+use std::str::{FromStr, SplitWhitespace};
+
+const MAGIC: &str = "breakdown-save";
+/// Bumped whenever the format changes; saves with another version are rejected.
+pub const VERSION: u32 = 1;
+
+#[derive(Debug)]
+pub enum SnapshotError {
+    /// Written by a version of the game that used another format.
+    Version(String),
+    /// The save is damaged.
+    Invalid { line: usize, message: String },
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Version(found) => write!(
+                f,
+                "unsupported save format `{}`, expected `{} {}`",
+                found, MAGIC, VERSION
+            ),
+            SnapshotError::Invalid { line, message } => write!(f, "{}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {}
+
+/// Everything needed to pick a run up where it was left: the board, the
+/// balls, the paddle, falling coins, running upgrades and the campaign
+/// progress.
+pub struct Snapshot {
+    pub level_index: usize,
+    /// Name of the level, to catch saves made with another set of levels.
+    pub level_name: String,
+    /// Score and lives when the level started.
+    pub checkpoint: (i32, i32),
+    score: i32,
+    lives: i32,
+    rng: Rng,
+    bounds: Rect,
+    paddle: Rect,
+    base_width: f32,
+    balls: Vec<Ball>,
+    blocks: Vec<Block>,
+    projectiles: Vec<Rect>,
+    coins: Vec<UpgradeCoin>,
+    effects: Vec<ActiveEffect>,
+}
+
+impl Snapshot {
+    pub fn capture(
+        world: &World,
+        level_index: usize,
+        level_name: &str,
+        checkpoint: (i32, i32),
+    ) -> Self {
+        Self {
+            level_index,
+            level_name: level_name.to_string(),
+            checkpoint,
+            score: world.score,
+            lives: world.lives,
+            rng: world.rng.clone(),
+            bounds: world.bounds,
+            paddle: world.player.rect,
+            base_width: world.player.base_width,
+            balls: world.balls.clone(),
+            blocks: world.blocks.clone(),
+            projectiles: world.projectiles.iter().map(|p| p.rect).collect(),
+            coins: world.upgrades.falling_upgrades.clone(),
+            effects: world.upgrades.effects.clone(),
+        }
+    }
+
+    /// Puts the snapshot into `world`. `level` must be the level it was
+    /// taken on; the level settings (drops, ball cap) come from there.
+    pub fn restore(self, world: &mut World, level: &Level) {
+        world.resize(self.bounds);
+        world.score = self.score;
+        world.lives = self.lives;
+        world.rng = self.rng;
+        world.max_balls = level.max_balls;
+        world.ball_cap_policy = level.ball_cap_policy;
+        world.drops = level.drops.clone();
+        world.player.restore(self.paddle, self.base_width);
+        world.balls = self.balls;
+        world.blocks = self.blocks;
+        let speed = PROJECTILE_SPEED * world.scale.total_scale;
+        world.projectiles = self
+            .projectiles
+            .into_iter()
+            .map(|rect| Projectile::new(rect.point(), rect.size(), speed))
+            .collect();
+        world.upgrades = Upgrades {
+            falling_upgrades: self.coins,
+            effects: self.effects,
+        };
+        world.events.clear();
+    }
+
+    /// Writes the snapshot as text, one object per line.
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![
+            format!("{} {}", MAGIC, VERSION),
+            format!("level {} {}", self.level_index, self.level_name),
+            format!("checkpoint {} {}", self.checkpoint.0, self.checkpoint.1),
+            format!("score {}", self.score),
+            format!("lives {}", self.lives),
+            format!("rng {}", self.rng.state()),
+            format!("bounds {}", rect_text(self.bounds)),
+            format!("paddle {} {}", rect_text(self.paddle), self.base_width),
+        ];
+        for ball in self.balls.iter() {
+            lines.push(format!(
+                "ball {} {} {} {} {} {} {}",
+                rect_text(ball.rect),
+                ball.vel.x,
+                ball.vel.y,
+                ball.speed,
+                ball.super_ball,
+                optional_text(ball.caught_offset),
+                ball.caught_time
+            ));
+        }
+        for block in self.blocks.iter() {
+            let (path_min, path_max) = match block.path {
+                Some((min, max)) => (Some(min), Some(max)),
+                None => (None, None),
+            };
+            lines.push(format!(
+                "block {} {} {} {} {} {} {} {}",
+                rect_text(block.rect),
+                block.lives,
+                block.max_lives,
+                block_type_name(block.block_type),
+                optional_text(block.upgrade_type.map(|t| t.name())),
+                optional_text(path_min),
+                optional_text(path_max),
+                block.velocity
+            ));
+        }
+        for rect in self.projectiles.iter() {
+            lines.push(format!("projectile {}", rect_text(*rect)));
+        }
+        for coin in self.coins.iter() {
+            lines.push(format!(
+                "coin {} {}",
+                coin.upgrade_type.name(),
+                rect_text(coin.rect)
+            ));
+        }
+        for effect in self.effects.iter() {
+            lines.push(format!(
+                "effect {} {} {} {}",
+                effect.upgrade_type.name(),
+                effect.remaining,
+                effect.duration,
+                effect.stacks
+            ));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    /// Reads a snapshot written by `to_text`. Saves from other versions and
+    /// damaged saves are rejected as a whole.
+    pub fn parse(text: &str) -> Result<Self, SnapshotError> {
+        let mut lines = text.lines().enumerate();
+        let header = lines.next().map_or("", |(_, line)| line.trim());
+        if header != format!("{} {}", MAGIC, VERSION) {
+            return Err(SnapshotError::Version(header.to_string()));
+        }
+
+        let mut level = None;
+        let mut checkpoint = None;
+        let mut score = None;
+        let mut lives = None;
+        let mut rng = None;
+        let mut bounds = None;
+        let mut paddle = None;
+        let mut balls = Vec::new();
+        let mut blocks = Vec::new();
+        let mut projectiles = Vec::new();
+        let mut coins = Vec::new();
+        let mut effects = Vec::new();
+        for (index, line) in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut fields = Fields {
+                line: index + 1,
+                fields: line.split_whitespace(),
+            };
+            let invalid = |message: String| SnapshotError::Invalid {
+                line: index + 1,
+                message,
+            };
+            match fields.word()? {
+                "level" => {
+                    let level_index = fields.number()?;
+                    let name = line.trim().splitn(3, ' ').nth(2).unwrap_or("");
+                    level = Some((level_index, name.to_string()));
+                    continue;
+                }
+                "checkpoint" => checkpoint = Some((fields.number()?, fields.number()?)),
+                "score" => score = Some(fields.number()?),
+                "lives" => lives = Some(fields.number()?),
+                "rng" => rng = Some(Rng::new(fields.number()?)),
+                "bounds" => bounds = Some(fields.rect()?),
+                "paddle" => paddle = Some((fields.rect()?, fields.number()?)),
+                "ball" => {
+                    let rect = fields.rect()?;
+                    balls.push(Ball {
+                        rect,
+                        prev_rect: rect,
+                        vel: vec2(fields.number()?, fields.number()?),
+                        speed: fields.number()?,
+                        super_ball: fields.number()?,
+                        caught_offset: fields.optional()?,
+                        caught_time: fields.number()?,
+                    });
+                }
+                "block" => {
+                    let rect = fields.rect()?;
+                    let lives = fields.number()?;
+                    let max_lives = fields.number()?;
+                    let type_name = fields.word()?;
+                    let block_type = block_type_from_name(type_name)
+                        .ok_or_else(|| invalid(format!("unknown block type `{}`", type_name)))?;
+                    let upgrade_type = match fields.word()? {
+                        "-" => None,
+                        name => Some(fields.upgrade(name)?),
+                    };
+                    let path = match (fields.optional()?, fields.optional()?) {
+                        (Some(min), Some(max)) => Some((min, max)),
+                        _ => None,
+                    };
+                    blocks.push(Block {
+                        rect,
+                        prev_rect: rect,
+                        lives,
+                        max_lives,
+                        block_type,
+                        upgrade_type,
+                        path,
+                        velocity: fields.number()?,
+                    });
+                }
+                "projectile" => projectiles.push(fields.rect()?),
+                "coin" => {
+                    let name = fields.word()?;
+                    let upgrade_type = fields.upgrade(name)?;
+                    let rect = fields.rect()?;
+                    coins.push(UpgradeCoin {
+                        upgrade_type,
+                        rect,
+                        prev_rect: rect,
+                    });
+                }
+                "effect" => {
+                    let name = fields.word()?;
+                    effects.push(ActiveEffect {
+                        upgrade_type: fields.upgrade(name)?,
+                        remaining: fields.number()?,
+                        duration: fields.number()?,
+                        stacks: fields.number()?,
+                    });
+                }
+                key => return Err(invalid(format!("unknown entry `{}`", key))),
+            }
+            if let Some(extra) = fields.fields.next() {
+                return Err(invalid(format!("unexpected `{}`", extra)));
+            }
+        }
+
+        let missing = |key: &str| SnapshotError::Invalid {
+            line: text.lines().count(),
+            message: format!("missing `{}`", key),
+        };
+        let (level_index, level_name) = level.ok_or_else(|| missing("level"))?;
+        let (paddle, base_width) = paddle.ok_or_else(|| missing("paddle"))?;
+        Ok(Self {
+            level_index,
+            level_name,
+            checkpoint: checkpoint.ok_or_else(|| missing("checkpoint"))?,
+            score: score.ok_or_else(|| missing("score"))?,
+            lives: lives.ok_or_else(|| missing("lives"))?,
+            rng: rng.ok_or_else(|| missing("rng"))?,
+            bounds: bounds.ok_or_else(|| missing("bounds"))?,
+            paddle,
+            base_width,
+            balls,
+            blocks,
+            projectiles,
+            coins,
+            effects,
+        })
+    }
+}
+
+/// The whitespace separated values of one line.
+struct Fields<'a> {
+    line: usize,
+    fields: SplitWhitespace<'a>,
+}
+
+impl<'a> Fields<'a> {
+    fn error(&self, message: String) -> SnapshotError {
+        SnapshotError::Invalid {
+            line: self.line,
+            message,
+        }
+    }
+
+    fn word(&mut self) -> Result<&'a str, SnapshotError> {
+        self.fields
+            .next()
+            .ok_or_else(|| self.error(String::from("unexpected end of line")))
+    }
+
+    fn number<T: FromStr>(&mut self) -> Result<T, SnapshotError> {
+        let word = self.word()?;
+        word.parse()
+            .map_err(|_| self.error(format!("invalid value `{}`", word)))
+    }
+
+    /// A number, or `-` for none.
+    fn optional<T: FromStr>(&mut self) -> Result<Option<T>, SnapshotError> {
+        let word = self.word()?;
+        match word {
+            "-" => Ok(None),
+            _ => word
+                .parse()
+                .map(Some)
+                .map_err(|_| self.error(format!("invalid value `{}`", word))),
+        }
+    }
+
+    fn rect(&mut self) -> Result<Rect, SnapshotError> {
+        Ok(Rect::new(
+            self.number()?,
+            self.number()?,
+            self.number()?,
+            self.number()?,
+        ))
+    }
+
+    fn upgrade(&self, name: &str) -> Result<UpgradeType, SnapshotError> {
+        UpgradeType::from_name(name)
+            .ok_or_else(|| self.error(format!("unknown upgrade `{}`", name)))
+    }
+}
+
+fn rect_text(rect: Rect) -> String {
+    format!("{} {} {} {}", rect.x, rect.y, rect.w, rect.h)
+}
+
+fn optional_text<T: fmt::Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => String::from("-"),
+    }
+}
+
+fn block_type_name(block_type: BlockType) -> &'static str {
+    match block_type {
+        BlockType::Regular => "regular",
+        BlockType::Upgrade => "upgrade",
+        BlockType::Steel => "steel",
+        BlockType::Explosive => "explosive",
+        BlockType::Moving => "moving",
+    }
+}
+
+fn block_type_from_name(name: &str) -> Option<BlockType> {
+    match name {
+        "regular" => Some(BlockType::Regular),
+        "upgrade" => Some(BlockType::Upgrade),
+        "steel" => Some(BlockType::Steel),
+        "explosive" => Some(BlockType::Explosive),
+        "moving" => Some(BlockType::Moving),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::game::input::InputSnapshot;
+    use crate::game::scale::Scale;
+
+    const DT: f32 = 1f32 / 120f32;
+
+    fn test_level() -> Level {
+        Level::parse("name: Test\nrandom_upgrades: 8\ngrid:\nRR*RR\n#~..R\n3RURR\n").unwrap()
+    }
+
+    fn test_world(level: &Level) -> World {
+        let bounds = Rect::new(0f32, 0f32, 800f32, 600f32);
+        World::new(bounds, Scale::for_width(0.8, 800f32, 800f32), level, 3)
+    }
+
+    fn run(world: &mut World, steps: usize) {
+        let input = InputSnapshot {
+            left: true,
+            fire: true,
+            ..Default::default()
+        };
+        for _ in 0..steps {
+            world.step(&input, DT);
+            world.events.drain_published().for_each(drop);
+        }
+    }
+
+    #[test]
+    fn round_trip_continues_exactly() {
+        let level = test_level();
+        let mut world = test_world(&level);
+        world.upgrades.activate(UpgradeType::SpaceInvader);
+        world
+            .upgrades
+            .spawn_upgrade(Rect::new(300f32, 200f32, 30f32, 30f32), UpgradeType::Magnet);
+        run(&mut world, 300);
+
+        let text = Snapshot::capture(&world, 0, &level.name, (0, 3)).to_text();
+        let snapshot = Snapshot::parse(&text).unwrap();
+        assert_eq!(
+            (snapshot.level_index, snapshot.level_name.as_str()),
+            (0, "Test")
+        );
+        assert_eq!(snapshot.checkpoint, (0, 3));
+        let mut restored = test_world(&level);
+        snapshot.restore(&mut restored, &level);
+        let restored_text = Snapshot::capture(&restored, 0, &level.name, (0, 3)).to_text();
+        assert_eq!(restored_text, text);
+
+        run(&mut world, 1_000);
+        run(&mut restored, 1_000);
+        assert_eq!(restored.score, world.score);
+        assert_eq!(restored.lives, world.lives);
+        assert_eq!(
+            Snapshot::capture(&restored, 0, &level.name, (0, 3)).to_text(),
+            Snapshot::capture(&world, 0, &level.name, (0, 3)).to_text()
+        );
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let level = test_level();
+        let text = Snapshot::capture(&test_world(&level), 0, &level.name, (0, 3)).to_text();
+        let text = text.replacen("breakdown-save 1", "breakdown-save 0", 1);
+        assert!(matches!(
+            Snapshot::parse(&text),
+            Err(SnapshotError::Version(found)) if found == "breakdown-save 0"
+        ));
+        assert!(matches!(
+            Snapshot::parse(""),
+            Err(SnapshotError::Version(_))
+        ));
+    }
+
+    #[test]
+    fn damaged_saves_are_rejected() {
+        let level = test_level();
+        let text = Snapshot::capture(&test_world(&level), 0, &level.name, (0, 3)).to_text();
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Cut off before the paddle
+        let truncated = lines[..7].join("\n");
+        assert!(matches!(
+            Snapshot::parse(&truncated),
+            Err(SnapshotError::Invalid { message, .. }) if message == "missing `paddle`"
+        ));
+
+        // A ball line missing its last values
+        let ball = lines
+            .iter()
+            .position(|line| line.starts_with("ball"))
+            .unwrap();
+        let mut damaged = lines.clone();
+        let short_ball = lines[ball].rsplitn(3, ' ').nth(2).unwrap().to_string();
+        damaged[ball] = &short_ball;
+        assert!(matches!(
+            Snapshot::parse(&damaged.join("\n")),
+            Err(SnapshotError::Invalid { line, .. }) if line == ball + 1
+        ));
+
+        let garbled = text.replace("score 0", "score lots");
+        assert!(matches!(
+            Snapshot::parse(&garbled),
+            Err(SnapshotError::Invalid { line: 4, .. })
+        ));
+        assert!(Snapshot::parse(&(text.clone() + "ghost 1 2\n")).is_err());
+        assert!(Snapshot::parse(&text.replace("regular", "rubber")).is_err());
+    }
+}
@@ -0,0 +1,90 @@
+use macroquad::math::Rect;
+
+/// Simulation ticks per second, unless the settings pick another rate.
+pub const DEFAULT_TICK_RATE: f32 = 120f32;
+/// Range accepted for the `tick_rate` setting.
+pub const MIN_TICK_RATE: f32 = 30f32;
+pub const MAX_TICK_RATE: f32 = 480f32;
+
+/// Turns variable frame times into a whole number of fixed simulation ticks,
+/// so gameplay runs the same regardless of the display refresh rate.
+pub struct FixedTimestep {
+    pub dt: f32,
+    accumulator: f32,
+}
+
+// Longest frame fed into the accumulator; after a long hitch the simulation
+// slows down instead of running hundreds of catch-up ticks.
+const MAX_FRAME_TIME: f32 = 0.25;
+
+impl FixedTimestep {
+    pub fn new(tick_rate: f32) -> Self {
+        Self {
+            dt: 1f32 / tick_rate,
+            accumulator: 0f32,
+        }
+    }
+
+    /// Adds the frame time and returns how many ticks should run this frame.
+    pub fn advance(&mut self, frame_time: f32) -> usize {
+        self.accumulator += frame_time.min(MAX_FRAME_TIME);
+        let ticks = (self.accumulator / self.dt) as usize;
+        self.accumulator -= ticks as f32 * self.dt;
+        ticks
+    }
+
+    /// How far the current frame is between the last tick and the next one.
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.dt).clamp(0f32, 1f32)
+    }
+
+    pub fn reset(&mut self) {
+        self.accumulator = 0f32;
+    }
+}
+
+pub fn interpolate(previous: Rect, current: Rect, alpha: f32) -> Rect {
+    Rect::new(
+        previous.x + (current.x - previous.x) * alpha,
+        previous.y + (current.y - previous.y) * alpha,
+        previous.w + (current.w - previous.w) * alpha,
+        previous.h + (current.h - previous.h) * alpha,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Frame times below are exact in binary at this rate, so tick counts
+    // don't depend on rounding.
+    const TICK_RATE: f32 = 128f32;
+
+    #[test]
+    fn uneven_frames_give_the_same_ticks_as_even_ones() {
+        let mut even = FixedTimestep::new(TICK_RATE);
+        let even_ticks: usize = (0..64).map(|_| even.advance(1f32 / 64f32)).sum();
+
+        let mut uneven = FixedTimestep::new(TICK_RATE);
+        let frames = [3f32 / 128f32, 1f32 / 128f32, 1f32 / 256f32, 7f32 / 256f32];
+        let uneven_ticks: usize = (0..16)
+            .flat_map(|_| frames)
+            .map(|frame| uneven.advance(frame))
+            .sum();
+
+        assert_eq!(even_ticks, 128);
+        assert_eq!(uneven_ticks, even_ticks);
+        assert_eq!(uneven.alpha(), 0f32);
+    }
+
+    #[test]
+    fn long_frames_are_clamped() {
+        let mut timestep = FixedTimestep::new(TICK_RATE);
+        assert_eq!(
+            timestep.advance(10f32),
+            (MAX_FRAME_TIME * TICK_RATE) as usize
+        );
+        // Nothing of the hitch is left over for the next frame.
+        assert_eq!(timestep.advance(0f32), 0);
+    }
+}
@@ -1,6 +1,11 @@
+use crate::game::events::{EventBus, GameEvent};
+use crate::game::timestep::interpolate;
 use macroquad::prelude::*;
 
-#[derive(Clone, Copy)]
+const FALL_SPEED: f32 = 60f32;
+const MAGNET_SPEED: f32 = 120f32;
+
+#[derive(Clone, Copy, PartialEq)]
 pub enum UpgradeType {
     Magnet,
     BallMultiplier,
@@ -8,106 +13,317 @@ pub enum UpgradeType {
     SuperBall,
     ExtraLife,
     SpaceInvader,
+    ExpandPaddle,
+    ShrinkPaddle,
+    FastBall,
+    ReverseControls,
+    Fog,
+    Death,
+}
+
+/// What happens when an upgrade is collected while it is still active.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stacking {
+    /// Restart the timer at the full duration.
+    Refresh,
+    /// Add the full duration to the remaining time, up to `max`.
+    Extend { max: f32 },
+    /// Add a stack, up to `max`, and restart the timer.
+    Stack { max: u32 },
+}
+
+impl UpgradeType {
+    pub const ALL: [UpgradeType; 12] = [
+        UpgradeType::Magnet,
+        UpgradeType::BallMultiplier,
+        UpgradeType::AddBall,
+        UpgradeType::SuperBall,
+        UpgradeType::ExtraLife,
+        UpgradeType::SpaceInvader,
+        UpgradeType::ExpandPaddle,
+        UpgradeType::ShrinkPaddle,
+        UpgradeType::FastBall,
+        UpgradeType::ReverseControls,
+        UpgradeType::Fog,
+        UpgradeType::Death,
+    ];
+
+    /// The name used for this upgrade in level files.
+    pub fn name(&self) -> &'static str {
+        match self {
+            UpgradeType::Magnet => "magnet",
+            UpgradeType::BallMultiplier => "ball_multiplier",
+            UpgradeType::AddBall => "add_ball",
+            UpgradeType::SuperBall => "super_ball",
+            UpgradeType::ExtraLife => "extra_life",
+            UpgradeType::SpaceInvader => "space_invader",
+            UpgradeType::ExpandPaddle => "expand_paddle",
+            UpgradeType::ShrinkPaddle => "shrink_paddle",
+            UpgradeType::FastBall => "fast_ball",
+            UpgradeType::ReverseControls => "reverse_controls",
+            UpgradeType::Fog => "fog",
+            UpgradeType::Death => "death",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        UpgradeType::ALL
+            .into_iter()
+            .find(|upgrade_type| upgrade_type.name() == name)
+    }
+
+    /// How long the upgrade stays active, or `None` if it applies instantly.
+    pub fn duration(&self) -> Option<f32> {
+        match self {
+            UpgradeType::Magnet => Some(15f32),
+            UpgradeType::SpaceInvader => Some(10f32),
+            UpgradeType::ExpandPaddle => Some(15f32),
+            UpgradeType::ShrinkPaddle => Some(10f32),
+            UpgradeType::FastBall => Some(8f32),
+            UpgradeType::ReverseControls => Some(6f32),
+            UpgradeType::Fog => Some(8f32),
+            UpgradeType::BallMultiplier
+            | UpgradeType::AddBall
+            | UpgradeType::SuperBall
+            | UpgradeType::ExtraLife
+            | UpgradeType::Death => None,
+        }
+    }
+
+    /// Whether catching this coin hurts the player.
+    pub fn is_hazard(&self) -> bool {
+        matches!(
+            self,
+            UpgradeType::ShrinkPaddle
+                | UpgradeType::FastBall
+                | UpgradeType::ReverseControls
+                | UpgradeType::Fog
+                | UpgradeType::Death
+        )
+    }
+
+    pub fn stacking(&self) -> Stacking {
+        match self {
+            UpgradeType::SpaceInvader => Stacking::Extend { max: 30f32 },
+            UpgradeType::ExpandPaddle | UpgradeType::ShrinkPaddle => Stacking::Stack { max: 3 },
+            _ => Stacking::Refresh,
+        }
+    }
+
+    pub fn color(&self) -> Color {
+        match self {
+            UpgradeType::Magnet => PINK,
+            UpgradeType::BallMultiplier => SKYBLUE,
+            UpgradeType::AddBall => PURPLE,
+            UpgradeType::SuperBall => VIOLET,
+            UpgradeType::ExtraLife => GOLD,
+            UpgradeType::SpaceInvader => BLACK,
+            UpgradeType::ExpandPaddle => DARKBLUE,
+            UpgradeType::ShrinkPaddle => BROWN,
+            UpgradeType::FastBall => ORANGE,
+            UpgradeType::ReverseControls => DARKPURPLE,
+            UpgradeType::Fog => LIGHTGRAY,
+            UpgradeType::Death => RED,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ActiveEffect {
+    pub upgrade_type: UpgradeType,
+    pub remaining: f32,
+    pub duration: f32,
+    pub stacks: u32,
 }
 
 pub struct Upgrades {
     pub falling_upgrades: Vec<UpgradeCoin>,
-    pub active_upgrades: Vec<UpgradeType>,
-    pub magnet_active: bool,
-    pub space_invader_active: bool,
+    pub effects: Vec<ActiveEffect>,
 }
 
 impl Upgrades {
     pub fn new() -> Self {
         Self {
             falling_upgrades: Vec::new(),
-            active_upgrades: Vec::new(),
-            magnet_active: false,
-            space_invader_active: false,
+            effects: Vec::new(),
         }
     }
 
-    pub fn spawn_upgrade(&mut self, originating_block: Rect) {
+    pub fn is_active(&self, upgrade_type: UpgradeType) -> bool {
+        self.effects
+            .iter()
+            .any(|effect| effect.upgrade_type == upgrade_type)
+    }
+
+    /// How many times a stacking upgrade is currently applied.
+    pub fn stacks(&self, upgrade_type: UpgradeType) -> u32 {
+        self.effects
+            .iter()
+            .find(|effect| effect.upgrade_type == upgrade_type)
+            .map_or(0, |effect| effect.stacks)
+    }
+
+    /// Starts the timer of a persistent upgrade, following its stacking rule
+    /// if it is already running.
+    pub fn activate(&mut self, upgrade_type: UpgradeType) {
+        let Some(duration) = upgrade_type.duration() else {
+            return;
+        };
+        match self
+            .effects
+            .iter_mut()
+            .find(|effect| effect.upgrade_type == upgrade_type)
+        {
+            Some(effect) => match upgrade_type.stacking() {
+                Stacking::Refresh => {
+                    effect.remaining = duration;
+                    effect.duration = duration;
+                }
+                Stacking::Extend { max } => {
+                    effect.remaining = (effect.remaining + duration).min(max);
+                    effect.duration = effect.duration.max(effect.remaining);
+                }
+                Stacking::Stack { max } => {
+                    effect.stacks = (effect.stacks + 1).min(max);
+                    effect.remaining = duration;
+                    effect.duration = duration;
+                }
+            },
+            None => self.effects.push(ActiveEffect {
+                upgrade_type,
+                remaining: duration,
+                duration,
+                stacks: 1,
+            }),
+        }
+    }
+
+    pub fn spawn_upgrade(&mut self, originating_block: Rect, upgrade_type: UpgradeType) {
         self.falling_upgrades
-            .push(UpgradeCoin::new(None, originating_block));
+            .push(UpgradeCoin::new(upgrade_type, originating_block));
     }
 
-    pub fn update(&mut self, player_rect: Rect) {
+    /// Moves the falling coins and emits `UpgradeCollected` for every coin
+    /// the paddle catches.
+    pub fn update(&mut self, player_rect: Rect, bounds: Rect, dt: f32, events: &mut EventBus) {
+        for effect in self.effects.iter_mut() {
+            effect.remaining -= dt;
+        }
+        self.effects.retain(|effect| effect.remaining > 0f32);
+
+        let magnet_active = self.is_active(UpgradeType::Magnet);
         for upgrade in &mut self.falling_upgrades {
-            if self.magnet_active {
+            upgrade.prev_rect = upgrade.rect;
+            if magnet_active {
                 let center = player_rect.point() + player_rect.size() * 0.5f32;
                 let to_center = center - upgrade.rect.point();
                 let to_center = to_center.normalize();
-                upgrade.rect.x += to_center.x * 2f32;
-                upgrade.rect.y += to_center.y * 2f32;
+                upgrade.rect.x += to_center.x * MAGNET_SPEED * dt;
+                upgrade.rect.y += to_center.y * MAGNET_SPEED * dt;
             } else {
-                upgrade.rect.y += 1f32;
+                upgrade.rect.y += FALL_SPEED * dt;
             }
         }
-        if let Some(upgrade) = self
-            .falling_upgrades
-            .iter()
-            .find(|upgrade| upgrade.rect.intersect(player_rect).is_some())
-        {
-            self.active_upgrades.push(upgrade.upgrade_type);
-        }
         self.falling_upgrades.retain(|upgrade| {
-            upgrade.rect.y < screen_height() && upgrade.rect.intersect(player_rect).is_none()
+            if upgrade.rect.intersect(player_rect).is_some() {
+                events.emit(GameEvent::UpgradeCollected {
+                    upgrade_type: upgrade.upgrade_type,
+                    rect: upgrade.rect,
+                });
+                return false;
+            }
+            upgrade.rect.y < bounds.bottom()
         });
     }
 
+    pub fn clear_falling(&mut self) {
+        self.falling_upgrades.clear();
+    }
+
     pub fn reset(&mut self) {
         self.falling_upgrades.clear();
-        self.active_upgrades.clear();
-        self.magnet_active = false;
-        self.space_invader_active = false;
+        self.effects.clear();
     }
 
-    pub fn draw(&mut self) {
+    pub fn draw(&mut self, alpha: f32) {
         for upgrade in &mut self.falling_upgrades {
-            let color: Color = match upgrade.upgrade_type {
-                UpgradeType::Magnet => PINK,
-                UpgradeType::BallMultiplier => SKYBLUE,
-                UpgradeType::AddBall => PURPLE,
-                UpgradeType::SuperBall => VIOLET,
-                UpgradeType::ExtraLife => GOLD,
-                UpgradeType::SpaceInvader => BLACK,
-            };
-
-            draw_rectangle(
-                upgrade.rect.x,
-                upgrade.rect.y,
-                upgrade.rect.w,
-                upgrade.rect.h,
-                color,
-            );
+            let rect = interpolate(upgrade.prev_rect, upgrade.rect, alpha);
+            draw_rectangle(rect.x, rect.y, rect.w, rect.h, upgrade.upgrade_type.color());
+            if upgrade.upgrade_type.is_hazard() {
+                draw_hazard_mark(rect);
+            }
         }
     }
+
+    /// Draws one icon per active effect, each with a bar showing the time
+    /// left, in a row starting at `pos`.
+    pub fn draw_effects(&self, pos: Vec2, icon_size: f32) {
+        let bar_width = icon_size * 3f32;
+        let bar_height = icon_size * 0.4f32;
+        let spacing = icon_size * 0.5f32;
+        let mut x = pos.x;
+        for effect in self.effects.iter() {
+            let color = effect.upgrade_type.color();
+            draw_rectangle(x, pos.y, icon_size, icon_size, color);
+            if effect.upgrade_type.is_hazard() {
+                draw_hazard_mark(Rect::new(x, pos.y, icon_size, icon_size));
+            }
+            // One pip per extra stack above the icon
+            let pip_size = icon_size * 0.25f32;
+            for stack in 1..effect.stacks {
+                let pip_x = x + (stack - 1) as f32 * pip_size * 1.5f32;
+                draw_rectangle(pip_x, pos.y - pip_size * 1.5f32, pip_size, pip_size, color);
+            }
+            let bar_x = x + icon_size + spacing * 0.5f32;
+            let bar_y = pos.y + (icon_size - bar_height) * 0.5f32;
+            let fill = (effect.remaining / effect.duration).clamp(0f32, 1f32);
+            draw_rectangle_lines(bar_x, bar_y, bar_width, bar_height, 1f32, DARKGRAY);
+            draw_rectangle(bar_x, bar_y, bar_width * fill, bar_height, color);
+            x = bar_x + bar_width + spacing;
+        }
+    }
+}
+
+fn draw_hazard_mark(rect: Rect) {
+    let thickness = (rect.w * 0.12f32).max(1f32);
+    draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, thickness, BLACK);
+    draw_line(
+        rect.x,
+        rect.y,
+        rect.right(),
+        rect.bottom(),
+        thickness,
+        BLACK,
+    );
+    draw_line(
+        rect.right(),
+        rect.y,
+        rect.x,
+        rect.bottom(),
+        thickness,
+        BLACK,
+    );
 }
+
+#[derive(Clone)]
 pub struct UpgradeCoin {
-    upgrade_type: UpgradeType,
+    pub upgrade_type: UpgradeType,
     pub rect: Rect,
+    pub prev_rect: Rect,
 }
 
 impl UpgradeCoin {
-    pub fn new(upgrade_type: Option<UpgradeType>, originating_block: Rect) -> Self {
-        let upgrade_type = upgrade_type.unwrap_or_else(|| match rand::gen_range(0, 6) {
-            0 => UpgradeType::Magnet,
-            1 => UpgradeType::BallMultiplier,
-            2 => UpgradeType::AddBall,
-            3 => UpgradeType::SuperBall,
-            4 => UpgradeType::ExtraLife,
-            _ => UpgradeType::SpaceInvader,
-        });
-
+    pub fn new(upgrade_type: UpgradeType, originating_block: Rect) -> Self {
+        let rect = Rect::new(
+            originating_block.x,
+            originating_block.y,
+            originating_block.w,
+            originating_block.h,
+        );
         Self {
             upgrade_type,
-            rect: Rect::new(
-                originating_block.x,
-                originating_block.y,
-                originating_block.w,
-                originating_block.h,
-            ),
+            rect,
+            prev_rect: rect,
         }
     }
 }
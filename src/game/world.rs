@@ -0,0 +1,881 @@
+use crate::game::ball::{Ball, BallCapPolicy, BounceModel};
+use crate::game::block::{Block, BlockType};
+use crate::game::collision::{self, Hit};
+use crate::game::drops::DropTable;
+use crate::game::events::{EventBus, GameEvent};
+use crate::game::grid::{self, SpatialGrid};
+use crate::game::input::{InputSnapshot, PointerControl};
+use crate::game::level::{Cell, Level};
+use crate::game::player::Player;
+use crate::game::projectile::Projectile;
+use crate::game::rng::Rng;
+use crate::game::scale::Scale;
+use crate::game::upgrades::{UpgradeType, Upgrades};
+use macroquad::math::{vec2, Rect, Vec2};
+
+pub const BLOCK_SIZE: f32 = 40.0;
+pub const BLOCK_PADDING: f32 = 5.0;
+pub const BOARD_TOP: f32 = 50.0;
+pub const PLAYER_SIZE: Vec2 = Vec2::from_array([150f32, 20f32]);
+pub const PLAYER_SPEED: f32 = 750f32;
+pub const PLAYER_RELATIVE_POS_Y: f32 = 50f32;
+pub const BALL_SIZE: f32 = 20f32;
+pub const BALL_SPEED: f32 = 400f32;
+pub const PROJECTILE_SIZE: Vec2 = Vec2::from_array([6f32, 16f32]);
+pub const PROJECTILE_SPEED: f32 = 900f32;
+pub const FIRE_INTERVAL: f32 = 0.3;
+pub const MAGNET_HOLD_TIME: f32 = 3f32;
+pub const BALL_CAP_SCORE: i32 = 5;
+pub const MOVING_BLOCK_SPEED: f32 = 80f32;
+// Blast radius of explosive blocks, in block spacings
+pub const EXPLOSION_RADIUS: f32 = 1.5;
+// Paddle width multiplier per expand / shrink stack, and overall limits
+pub const EXPAND_PADDLE_FACTOR: f32 = 1.3;
+pub const SHRINK_PADDLE_FACTOR: f32 = 0.7;
+pub const MIN_PADDLE_FACTOR: f32 = 0.4;
+pub const MAX_PADDLE_FACTOR: f32 = 2.2;
+pub const FAST_BALL_FACTOR: f32 = 1.5;
+const MAX_BOUNCES_PER_STEP: usize = 8;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum StepOutcome {
+    Playing,
+    LifeLost,
+    GameOver,
+    LevelCleared,
+}
+
+/// The gameplay simulation.
+///
+/// `World` never touches the window, the keyboard or the global random
+/// generator: bounds, input, dt and the seed are all passed in, so it can be
+/// stepped without a display.
+pub struct World {
+    pub bounds: Rect,
+    pub scale: Scale,
+    pub player: Player,
+    pub balls: Vec<Ball>,
+    pub blocks: Vec<Block>,
+    pub projectiles: Vec<Projectile>,
+    pub upgrades: Upgrades,
+    pub drops: DropTable,
+    pub score: i32,
+    pub lives: i32,
+    pub rng: Rng,
+    pub bounce_model: BounceModel,
+    /// Fire automatically while the space invader upgrade is active instead
+    /// of waiting for the fire input.
+    pub auto_fire: bool,
+    pub pointer_control: PointerControl,
+    pub max_balls: usize,
+    pub ball_cap_policy: BallCapPolicy,
+    fire_cooldown: f32,
+    /// Death coins collected this step, taking their lives after the other
+    /// upgrades are applied.
+    deaths_collected: i32,
+    /// Only test blocks near each moving object, found through `block_grid`.
+    /// Turning it off tests every block, which is only useful for comparison.
+    pub broad_phase: bool,
+    block_grid: SpatialGrid,
+    candidates: Vec<usize>,
+    pub events: EventBus,
+}
+
+impl World {
+    pub fn new(bounds: Rect, scale: Scale, level: &Level, seed: u64) -> Self {
+        let mut rng = Rng::new(seed);
+        let ball_position = vec2(
+            bounds.w * 0.5f32,
+            bounds.bottom() - PLAYER_RELATIVE_POS_Y - PLAYER_SIZE.y,
+        );
+        let balls = vec![Ball::new(
+            ball_position,
+            BALL_SIZE * scale.total_scale,
+            BALL_SPEED * scale.total_scale,
+            &mut rng,
+        )];
+        let blocks = World::generate_blocks(level, bounds, scale, &mut rng);
+        Self {
+            bounds,
+            scale,
+            player: Player::new(
+                PLAYER_SIZE,
+                PLAYER_SPEED,
+                PLAYER_RELATIVE_POS_Y,
+                scale,
+                bounds,
+            ),
+            balls,
+            blocks,
+            projectiles: Vec::new(),
+            upgrades: Upgrades::new(),
+            drops: level.drops.clone(),
+            score: 0,
+            lives: level.lives,
+            rng,
+            bounce_model: BounceModel::default(),
+            auto_fire: false,
+            pointer_control: PointerControl::default(),
+            max_balls: level.max_balls,
+            ball_cap_policy: level.ball_cap_policy,
+            fire_cooldown: 0f32,
+            deaths_collected: 0,
+            broad_phase: true,
+            block_grid: SpatialGrid::new(),
+            candidates: Vec::new(),
+            events: EventBus::new(),
+        }
+    }
+
+    fn generate_blocks(level: &Level, bounds: Rect, scale: Scale, rng: &mut Rng) -> Vec<Block> {
+        let mut blocks = Vec::new();
+        let (width, height) = (level.width, level.height);
+        let block_size: f32 = BLOCK_SIZE * scale.total_scale;
+        let total_block_size = vec2(block_size, block_size) + vec2(BLOCK_PADDING, BLOCK_PADDING);
+        let board_start_pos = vec2(
+            bounds.x + (bounds.w - (total_block_size.x * width as f32)) * 0.5f32,
+            bounds.y + BOARD_TOP,
+        );
+
+        for i in 0..width * height {
+            let (block_type, upgrade_type, lives) = match level.cells[i] {
+                Cell::Empty => continue,
+                Cell::Regular => (BlockType::Regular, None, level.block_lives),
+                Cell::MultiHit(lives) => (BlockType::Regular, None, lives),
+                Cell::Upgrade(upgrade_type) => {
+                    (BlockType::Upgrade, upgrade_type, level.block_lives)
+                }
+                Cell::Steel => (BlockType::Steel, None, 1),
+                Cell::Explosive => (BlockType::Explosive, None, 1),
+                Cell::Moving(..) => (BlockType::Moving, None, level.block_lives),
+            };
+            let block_x = (i % width) as f32 * total_block_size.x;
+            let block_y = (i / width) as f32 * total_block_size.y;
+            let mut block = Block::new(
+                board_start_pos + vec2(block_x, block_y),
+                block_type,
+                block_size,
+                lives,
+            );
+            block.upgrade_type = upgrade_type;
+            if let Cell::Moving(left, right) = level.cells[i] {
+                block.path = Some((
+                    board_start_pos.x + left as f32 * total_block_size.x,
+                    board_start_pos.x + right as f32 * total_block_size.x,
+                ));
+                block.velocity = MOVING_BLOCK_SPEED * scale.total_scale;
+            }
+            blocks.push(block);
+        }
+
+        let regular: Vec<usize> = (0..blocks.len())
+            .filter(|index| blocks[*index].block_type == BlockType::Regular)
+            .collect();
+        if !regular.is_empty() {
+            for _ in 0..level.random_upgrades {
+                let rand_index = regular[rng.gen_range_usize(0, regular.len())];
+                blocks[rand_index].block_type = BlockType::Upgrade;
+            }
+        }
+
+        for upgrade_type in level.guaranteed_drops.iter() {
+            let free: Vec<usize> = (0..blocks.len())
+                .filter(|index| {
+                    matches!(
+                        blocks[*index].block_type,
+                        BlockType::Regular | BlockType::Upgrade
+                    ) && blocks[*index].upgrade_type.is_none()
+                })
+                .collect();
+            if free.is_empty() {
+                break;
+            }
+            let block = &mut blocks[free[rng.gen_range_usize(0, free.len())]];
+            block.block_type = BlockType::Upgrade;
+            block.upgrade_type = Some(*upgrade_type);
+        }
+
+        blocks
+    }
+
+    pub fn resize(&mut self, bounds: Rect) {
+        self.bounds = bounds;
+        self.scale.update_for_width(bounds.w);
+    }
+
+    fn center_player(&mut self) {
+        self.player.rect.x = self.bounds.w * 0.5f32 - self.player.rect.w * 0.5f32;
+        self.player.prev_rect = self.player.rect;
+    }
+
+    /// Starts a new run on `level`. The same seed, bounds and inputs always
+    /// play out the same way.
+    pub fn reset(&mut self, level: &Level, seed: u64) {
+        self.rng = Rng::new(seed);
+        self.score = 0;
+        self.max_balls = level.max_balls;
+        self.ball_cap_policy = level.ball_cap_policy;
+        self.lives = level.lives;
+        // Rebuilt so the paddle speed follows the current scale.
+        self.player = Player::new(
+            PLAYER_SIZE,
+            PLAYER_SPEED,
+            PLAYER_RELATIVE_POS_Y,
+            self.scale,
+            self.bounds,
+        );
+        self.center_player();
+        self.balls = vec![self.new_ball_next_to_player()];
+        self.blocks = World::generate_blocks(level, self.bounds, self.scale, &mut self.rng);
+        self.projectiles.clear();
+        self.events.clear();
+        self.upgrades = Upgrades::new();
+        self.fire_cooldown = 0f32;
+        self.drops = level.drops.clone();
+    }
+
+    /// Moves on to `level`; score, lives and active upgrades carry over.
+    pub fn load_level(&mut self, level: &Level) {
+        self.max_balls = level.max_balls;
+        self.ball_cap_policy = level.ball_cap_policy;
+        self.center_player();
+        self.balls.clear();
+        self.blocks = World::generate_blocks(level, self.bounds, self.scale, &mut self.rng);
+        self.projectiles.clear();
+        self.events.clear();
+        self.upgrades.clear_falling();
+        self.drops = level.drops.clone();
+    }
+
+    /// Starts `level` over with the given score and lives and no running
+    /// upgrades.
+    pub fn restart_level(&mut self, level: &Level, score: i32, lives: i32) {
+        self.load_level(level);
+        self.score = score;
+        self.lives = lives;
+        self.upgrades.reset();
+        self.player.reset_width();
+    }
+
+    fn ball_position_next_to_player(&self) -> Vec2 {
+        self.player.rect.point()
+            + vec2(
+                self.player.rect.w * 0.5f32 - BALL_SIZE * 0.5f32,
+                -PLAYER_SIZE.y,
+            )
+    }
+
+    fn new_ball_next_to_player(&mut self) -> Ball {
+        Ball::new(
+            self.ball_position_next_to_player(),
+            BALL_SIZE * self.scale.total_scale,
+            BALL_SPEED * self.scale.total_scale,
+            &mut self.rng,
+        )
+    }
+
+    fn new_super_ball_next_to_player(&mut self) -> Ball {
+        Ball::new_super_ball(
+            self.ball_position_next_to_player(),
+            BALL_SIZE * self.scale.total_scale,
+            BALL_SPEED * self.scale.total_scale,
+            &mut self.rng,
+        )
+    }
+
+    pub fn spawn_ball_next_to_player(&mut self) {
+        let ball = self.new_ball_next_to_player();
+        self.balls.push(ball);
+    }
+
+    pub fn update_player(&mut self, input: &InputSnapshot, dt: f32) {
+        let mut input = *input;
+        if self.upgrades.is_active(UpgradeType::ReverseControls) {
+            std::mem::swap(&mut input.left, &mut input.right);
+            input.pointer_x = input
+                .pointer_x
+                .map(|x| self.bounds.left() + self.bounds.right() - x);
+        }
+        self.player.resize_to(self.paddle_width());
+        self.player
+            .update(dt, &input, self.bounds, self.pointer_control);
+    }
+
+    fn paddle_width(&self) -> f32 {
+        let expand = self.upgrades.stacks(UpgradeType::ExpandPaddle) as i32;
+        let shrink = self.upgrades.stacks(UpgradeType::ShrinkPaddle) as i32;
+        let factor = EXPAND_PADDLE_FACTOR.powi(expand) * SHRINK_PADDLE_FACTOR.powi(shrink);
+        self.player.base_width * factor.clamp(MIN_PADDLE_FACTOR, MAX_PADDLE_FACTOR)
+    }
+
+    /// Takes a life and drops every running upgrade.
+    fn lose_life(&mut self) {
+        self.lives -= 1;
+        self.events.emit(GameEvent::LifeLost);
+        self.upgrades.reset();
+        self.player.reset_width();
+    }
+
+    pub fn step(&mut self, input: &InputSnapshot, dt: f32) -> StepOutcome {
+        self.update_player(input, dt);
+        for block in self.blocks.iter_mut() {
+            block.update(dt);
+        }
+        self.rebuild_block_grid();
+        self.update_caught_balls(input, dt);
+        self.move_balls(dt);
+        self.fire_projectiles(input, dt);
+        self.move_projectiles(dt);
+
+        self.upgrades
+            .update(self.player.rect, self.bounds, dt, &mut self.events);
+
+        let mut outcome = StepOutcome::Playing;
+        let balls_len = self.balls.len();
+        // Remove balls below the screen
+        let bottom = self.bounds.bottom();
+        let events = &mut self.events;
+        self.balls.retain(|ball| {
+            if ball.rect.y < bottom {
+                return true;
+            }
+            events.emit(GameEvent::BallLost { rect: ball.rect });
+            false
+        });
+
+        let removed_balls = balls_len - self.balls.len();
+        if removed_balls > 0 && self.balls.is_empty() {
+            self.lose_life();
+            outcome = StepOutcome::LifeLost;
+        }
+
+        self.dispatch_events();
+        // Losing a life drops every running upgrade, including the ones
+        // collected in the same step as the death coin.
+        for _ in 0..std::mem::take(&mut self.deaths_collected) {
+            self.lose_life();
+        }
+        self.dispatch_events();
+        // Explosions destroy blocks while the events are dispatched.
+        self.blocks.retain(|block| block.lives > 0);
+        if !self.blocks.iter().any(Block::is_breakable) {
+            self.events.emit(GameEvent::LevelCleared);
+            self.dispatch_events();
+            outcome = StepOutcome::LevelCleared;
+        }
+        if self.lives <= 0 {
+            outcome = StepOutcome::GameOver;
+        }
+        outcome
+    }
+
+    /// Runs the simulation systems for every event emitted this step,
+    /// including the ones they emit themselves, and publishes them.
+    fn dispatch_events(&mut self) {
+        while let Some(event) = self.events.next_pending() {
+            self.score_system(&event);
+            self.drop_system(&event);
+            self.explosion_system(&event);
+            self.upgrade_system(&event);
+        }
+    }
+
+    fn score_system(&mut self, event: &GameEvent) {
+        if let GameEvent::BlockDestroyed { score, .. } = event {
+            self.score += score;
+        }
+    }
+
+    fn drop_system(&mut self, event: &GameEvent) {
+        let GameEvent::BlockDestroyed {
+            rect,
+            block_type,
+            upgrade_type,
+            ..
+        } = *event
+        else {
+            return;
+        };
+        if let Some(upgrade_type) = self.drops.drop_for(
+            block_type == BlockType::Upgrade,
+            upgrade_type,
+            &mut self.rng,
+        ) {
+            self.upgrades.spawn_upgrade(rect, upgrade_type);
+        }
+    }
+
+    fn explosion_system(&mut self, event: &GameEvent) {
+        if let GameEvent::BlockDestroyed {
+            rect,
+            block_type: BlockType::Explosive,
+            ..
+        } = *event
+        {
+            self.explode(rect.center());
+        }
+    }
+
+    fn rebuild_block_grid(&mut self) {
+        // Without the broad phase a single cell holds every block.
+        let cell_size = match self.broad_phase {
+            true => BLOCK_SIZE * self.scale.total_scale + BLOCK_PADDING,
+            false => self.bounds.w.max(self.bounds.h),
+        };
+        self.block_grid.rebuild(
+            self.bounds,
+            cell_size,
+            self.blocks.iter().map(|block| block.rect),
+        );
+    }
+
+    /// Moves every ball along its path for `dt`, bouncing off the earliest
+    /// obstacle each time, so fast balls can't skip over thin obstacles.
+    fn move_balls(&mut self, dt: f32) {
+        let walls = collision::walls(self.bounds);
+        let dt = match self.upgrades.is_active(UpgradeType::FastBall) {
+            true => dt * FAST_BALL_FACTOR,
+            false => dt,
+        };
+        for ball in self.balls.iter_mut() {
+            if ball.caught_offset.is_some() {
+                continue;
+            }
+            ball.prev_rect = ball.rect;
+            let mut remaining = 1f32;
+            for _ in 0..MAX_BOUNCES_PER_STEP {
+                let delta = ball.delta(dt) * remaining;
+                self.block_grid
+                    .query(grid::swept_area(ball.rect, delta), &mut self.candidates);
+                let Some((hit, obstacle)) = earliest_hit(
+                    ball.rect,
+                    delta,
+                    &walls,
+                    self.player.rect,
+                    &self.blocks,
+                    &self.candidates,
+                ) else {
+                    ball.rect = ball.rect.offset(delta);
+                    break;
+                };
+
+                ball.rect = ball.rect.offset(delta * hit.time);
+                remaining *= 1f32 - hit.time;
+                match obstacle {
+                    Obstacle::Paddle
+                        if hit.normal.y < 0f32 && self.upgrades.is_active(UpgradeType::Magnet) =>
+                    {
+                        ball.catch(self.player.rect, MAGNET_HOLD_TIME)
+                    }
+                    Obstacle::Paddle if hit.normal.y < 0f32 => {
+                        ball.deflect_off_paddle(self.player.rect, self.player.velocity)
+                    }
+                    _ => ball.reflect(hit.normal, &self.bounce_model, &mut self.rng),
+                }
+
+                match obstacle {
+                    Obstacle::Wall => {}
+                    Obstacle::Paddle => self.events.emit(GameEvent::PaddleHit),
+                    Obstacle::Block(index) => {
+                        let block = &mut self.blocks[index];
+                        self.events.emit(GameEvent::BlockHit { rect: block.rect });
+                        if block.hit(ball.super_ball) {
+                            self.events.emit(block_destroyed(block));
+                        }
+                    }
+                }
+                if ball.caught_offset.is_some() {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Keeps balls held by the magnet on the paddle and launches them on a
+    /// fresh launch press or once their hold time runs out. Holding launch
+    /// (it shares a key with fire) doesn't let go of them.
+    fn update_caught_balls(&mut self, input: &InputSnapshot, dt: f32) {
+        for ball in self.balls.iter_mut() {
+            if ball.caught_offset.is_none() {
+                continue;
+            }
+            ball.prev_rect = ball.rect;
+            ball.follow(self.player.rect);
+            ball.caught_time -= dt;
+            if input.launch_pressed || ball.caught_time <= 0f32 {
+                ball.release(self.player.rect, self.player.velocity);
+            }
+        }
+    }
+
+    /// Hits every block in the blast radius around `center`. Explosive blocks
+    /// destroyed by the blast set off their own explosions when their events
+    /// are dispatched.
+    fn explode(&mut self, center: Vec2) {
+        let radius = (BLOCK_SIZE * self.scale.total_scale + BLOCK_PADDING) * EXPLOSION_RADIUS;
+        self.events.emit(GameEvent::Explosion { center, radius });
+        for block in self.blocks.iter_mut() {
+            if block.rect.center().distance(center) <= radius && block.hit(false) {
+                self.events.emit(block_destroyed(block));
+            }
+        }
+    }
+
+    /// Fires a pair of projectiles from the paddle edges while the space
+    /// invader upgrade is active.
+    fn fire_projectiles(&mut self, input: &InputSnapshot, dt: f32) {
+        self.fire_cooldown = (self.fire_cooldown - dt).max(0f32);
+        if !self.upgrades.is_active(UpgradeType::SpaceInvader)
+            || !(input.fire || self.auto_fire)
+            || self.fire_cooldown > 0f32
+        {
+            return;
+        }
+        let size = PROJECTILE_SIZE * self.scale.total_scale;
+        let paddle = self.player.rect;
+        for x in [paddle.left(), paddle.right() - size.x] {
+            self.projectiles.push(Projectile::new(
+                vec2(x, paddle.top() - size.y),
+                size,
+                PROJECTILE_SPEED * self.scale.total_scale,
+            ));
+        }
+        self.fire_cooldown = FIRE_INTERVAL;
+        self.events.emit(GameEvent::ProjectileFired);
+    }
+
+    fn move_projectiles(&mut self, dt: f32) {
+        let top = self.bounds.top();
+        let blocks = &mut self.blocks;
+        let block_grid = &self.block_grid;
+        let candidates = &mut self.candidates;
+        let events = &mut self.events;
+        self.projectiles.retain_mut(|projectile| {
+            projectile.prev_rect = projectile.rect;
+            let delta = projectile.delta(dt);
+            block_grid.query(grid::swept_area(projectile.rect, delta), candidates);
+            let hit = candidates
+                .iter()
+                .map(|&index| (index, &blocks[index]))
+                .filter(|(_, block)| block.lives > 0)
+                .filter_map(|(index, block)| {
+                    collision::sweep(projectile.rect, delta, block.rect).map(|hit| (hit, index))
+                })
+                .min_by(|(a, _), (b, _)| a.time.total_cmp(&b.time));
+            match hit {
+                Some((_, index)) => {
+                    let block = &mut blocks[index];
+                    events.emit(GameEvent::BlockHit { rect: block.rect });
+                    if block.hit(false) {
+                        events.emit(block_destroyed(block));
+                    }
+                    false
+                }
+                None => {
+                    projectile.rect = projectile.rect.offset(delta);
+                    projectile.rect.bottom() > top
+                }
+            }
+        });
+    }
+
+    /// Adds an extra ball to play, following `ball_cap_policy` once there
+    /// are `max_balls` of them.
+    fn add_ball(&mut self, ball: Ball) {
+        if self.balls.len() < self.max_balls {
+            self.balls.push(ball);
+            return;
+        }
+        match self.ball_cap_policy {
+            BallCapPolicy::Score => self.score += BALL_CAP_SCORE,
+            BallCapPolicy::SuperBall => {
+                if let Some(existing) = self.balls.iter_mut().find(|ball| !ball.super_ball) {
+                    existing.super_ball = true;
+                }
+            }
+            BallCapPolicy::Ignore => {}
+        }
+    }
+
+    /// Applies collected upgrades.
+    fn upgrade_system(&mut self, event: &GameEvent) {
+        let GameEvent::UpgradeCollected { upgrade_type, .. } = *event else {
+            return;
+        };
+        match upgrade_type {
+            UpgradeType::AddBall => {
+                let ball = self.new_ball_next_to_player();
+                self.add_ball(ball);
+            }
+            UpgradeType::ExtraLife => {
+                self.lives += 1;
+                self.events.emit(GameEvent::LifeGained);
+            }
+            UpgradeType::SuperBall => {
+                let ball = self.new_super_ball_next_to_player();
+                self.add_ball(ball);
+            }
+            UpgradeType::BallMultiplier => {
+                let mut new_balls: Vec<Ball> = vec![];
+                for ball in self.balls.iter() {
+                    let mut new_ball: Ball = match ball.super_ball {
+                        true => Ball::new_super_ball(
+                            ball.rect.point(),
+                            ball.rect.w,
+                            ball.speed,
+                            &mut self.rng,
+                        ),
+                        false => {
+                            Ball::new(ball.rect.point(), ball.rect.w, ball.speed, &mut self.rng)
+                        }
+                    };
+                    new_ball.random_direction(&mut self.rng);
+                    new_balls.push(new_ball);
+                }
+                for ball in new_balls {
+                    self.add_ball(ball);
+                }
+            }
+            UpgradeType::SpaceInvader
+            | UpgradeType::Magnet
+            | UpgradeType::ExpandPaddle
+            | UpgradeType::ShrinkPaddle
+            | UpgradeType::FastBall
+            | UpgradeType::ReverseControls
+            | UpgradeType::Fog => {
+                self.upgrades.activate(upgrade_type);
+            }
+            UpgradeType::Death => self.deaths_collected += 1,
+        }
+    }
+}
+
+fn block_destroyed(block: &Block) -> GameEvent {
+    GameEvent::BlockDestroyed {
+        rect: block.rect,
+        block_type: block.block_type,
+        upgrade_type: block.upgrade_type,
+        score: block.score(),
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Obstacle {
+    Wall,
+    Paddle,
+    Block(usize),
+}
+
+fn earliest_hit(
+    rect: Rect,
+    delta: Vec2,
+    walls: &[Rect],
+    paddle: Rect,
+    blocks: &[Block],
+    candidates: &[usize],
+) -> Option<(Hit, Obstacle)> {
+    let walls = walls.iter().map(|wall| (*wall, Obstacle::Wall));
+    let paddle = std::iter::once((paddle, Obstacle::Paddle));
+    let blocks = candidates
+        .iter()
+        .map(|&index| (index, &blocks[index]))
+        .filter(|(_, block)| block.lives > 0)
+        .map(|(index, block)| (block.rect, Obstacle::Block(index)));
+
+    walls
+        .chain(paddle)
+        .chain(blocks)
+        .filter_map(|(target, obstacle)| {
+            collision::sweep(rect, delta, target).map(|hit| (hit, obstacle))
+        })
+        .min_by(|(a, _), (b, _)| a.time.total_cmp(&b.time))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DT: f32 = 1f32 / 120f32;
+
+    fn test_world(source: &str) -> World {
+        let bounds = Rect::new(0f32, 0f32, 800f32, 600f32);
+        let scale = Scale::for_width(0.8, 800f32, bounds.w);
+        let level = Level::parse(source).expect("test level is valid");
+        World::new(bounds, scale, &level, 7)
+    }
+
+    /// Replaces the balls with one at `position` heading along `vel`.
+    fn place_ball(world: &mut World, position: Vec2, vel: Vec2) {
+        let mut ball = Ball::new(
+            position,
+            BALL_SIZE * world.scale.total_scale,
+            BALL_SPEED * world.scale.total_scale,
+            &mut world.rng,
+        );
+        ball.vel = vel.normalize();
+        world.balls = vec![ball];
+    }
+
+    /// Steps until something other than `Playing` happens.
+    fn run_until_outcome(world: &mut World, dt: f32) -> StepOutcome {
+        for _ in 0..10_000 {
+            let outcome = world.step(&InputSnapshot::default(), dt);
+            if outcome != StepOutcome::Playing {
+                return outcome;
+            }
+            world.events.drain_published().for_each(drop);
+        }
+        panic!("nothing happened");
+    }
+
+    #[test]
+    fn breaking_the_last_block_clears_the_level() {
+        let mut world = test_world("lives: 2\nblock_lives: 1\ngrid:\n.R.\n");
+        let block = world.blocks[0].rect;
+        let ball_x = block.center().x - BALL_SIZE * world.scale.total_scale * 0.5f32;
+        place_ball(&mut world, vec2(ball_x, 300f32), vec2(0f32, -1f32));
+
+        assert!(run_until_outcome(&mut world, DT) == StepOutcome::LevelCleared);
+        assert_eq!(world.score, 5);
+        assert_eq!(world.lives, 2);
+        assert!(world.blocks.is_empty());
+    }
+
+    #[test]
+    fn explosion_clears_the_level_in_the_same_step() {
+        let mut world = test_world("block_lives: 1\ngrid:\n*R\n");
+        let block = world.blocks[0].rect;
+        let ball_x = block.center().x - BALL_SIZE * world.scale.total_scale * 0.5f32;
+        place_ball(&mut world, vec2(ball_x, 300f32), vec2(0f32, -1f32));
+
+        let mut outcome = StepOutcome::Playing;
+        while world.score == 0 {
+            world.events.drain_published().for_each(drop);
+            outcome = world.step(&InputSnapshot::default(), DT);
+        }
+        // The step that hit the explosive also took the block next to it.
+        assert!(outcome == StepOutcome::LevelCleared);
+        assert!(world.blocks.is_empty());
+        assert_eq!(world.score, 30);
+        let events: Vec<GameEvent> = world.events.drain_published().collect();
+        assert!(events.last() == Some(&GameEvent::LevelCleared));
+    }
+
+    #[test]
+    fn losing_the_last_ball_costs_a_life_then_ends_the_game() {
+        let mut world = test_world("lives: 2\ngrid:\nR\n");
+        place_ball(&mut world, vec2(10f32, 560f32), vec2(0f32, 1f32));
+        assert!(run_until_outcome(&mut world, DT) == StepOutcome::LifeLost);
+        assert_eq!(world.lives, 1);
+
+        place_ball(&mut world, vec2(10f32, 560f32), vec2(0f32, 1f32));
+        assert!(run_until_outcome(&mut world, DT) == StepOutcome::GameOver);
+        assert_eq!(world.lives, 0);
+        assert_eq!(world.score, 0);
+    }
+
+    #[test]
+    fn frame_hitch_still_hits_the_block_in_front() {
+        let mut world = test_world("block_lives: 1\ngrid:\n.R.\n");
+        let block = world.blocks[0].rect;
+        let ball_x = block.center().x - BALL_SIZE * world.scale.total_scale * 0.5f32;
+        place_ball(&mut world, vec2(ball_x, 300f32), vec2(0f32, -1f32));
+
+        // One step long enough to cover the distance to the block several times
+        let outcome = world.step(&InputSnapshot::default(), 2f32);
+        assert!(outcome == StepOutcome::LevelCleared);
+        assert_eq!(world.score, 5);
+        let ball = world.balls[0].rect;
+        assert!(ball.top() >= block.bottom() - 1e-3);
+        assert!(world.balls[0].vel.y > 0f32);
+    }
+
+    #[test]
+    fn frame_hitches_keep_the_ball_inside_the_bounds() {
+        // Steel blocks keep the board from being cleared.
+        let mut world = test_world("grid:\n#R#\n");
+        place_ball(&mut world, vec2(400f32, 300f32), vec2(0.8, -0.6));
+        world.bounce_model.jitter = 0f32;
+        let bounds = world.bounds;
+        for _ in 0..50 {
+            world.step(&InputSnapshot::default(), 0.5);
+            let Some(ball) = world.balls.first() else {
+                break;
+            };
+            assert!(ball.rect.left() >= bounds.left() - 1e-3);
+            assert!(ball.rect.right() <= bounds.right() + 1e-3);
+            assert!(ball.rect.top() >= bounds.top() - 1e-3);
+            for block in world.blocks.iter() {
+                let overlap = ball.rect.intersect(block.rect);
+                assert!(overlap.is_none_or(|overlap| overlap.w < 1e-3 || overlap.h < 1e-3));
+            }
+        }
+    }
+
+    #[test]
+    fn magnet_keeps_the_ball_until_launch_is_pressed_again() {
+        let mut world = test_world("grid:\nR\n");
+        world.upgrades.activate(UpgradeType::Magnet);
+        let paddle = world.player.rect;
+        let ball_x = paddle.center().x - BALL_SIZE * world.scale.total_scale * 0.5f32;
+        place_ball(
+            &mut world,
+            vec2(ball_x, paddle.top() - 60f32),
+            vec2(0f32, 1f32),
+        );
+
+        let held = InputSnapshot {
+            launch: true,
+            ..Default::default()
+        };
+        for _ in 0..120 {
+            world.step(&held, DT);
+        }
+        assert!(world.balls[0].caught_offset.is_some());
+
+        let pressed = InputSnapshot {
+            launch: true,
+            launch_pressed: true,
+            ..Default::default()
+        };
+        world.step(&pressed, DT);
+        assert!(world.balls[0].caught_offset.is_none());
+        assert!(world.balls[0].vel.y < 0f32);
+    }
+
+    #[test]
+    fn death_drops_upgrades_collected_in_the_same_step() {
+        let mut world = test_world("lives: 3\ngrid:\nR\n");
+        world.upgrades.activate(UpgradeType::ExpandPaddle);
+        // Both land on the paddle in the first step, the death coin first.
+        let paddle = world.player.rect;
+        world.upgrades.spawn_upgrade(paddle, UpgradeType::Death);
+        world.upgrades.spawn_upgrade(paddle, UpgradeType::Magnet);
+
+        world.step(&InputSnapshot::default(), DT);
+        assert_eq!(world.lives, 2);
+        assert!(!world.upgrades.is_active(UpgradeType::Magnet));
+        assert!(!world.upgrades.is_active(UpgradeType::ExpandPaddle));
+        let events: Vec<GameEvent> = world.events.drain_published().collect();
+        assert!(events.contains(&GameEvent::LifeLost));
+    }
+
+    #[test]
+    fn same_seed_and_input_play_out_the_same() {
+        let source = "random_upgrades: 10\ngrid:\nRRRRRRRRRR\nRRRRRRRRRR\n";
+        let mut a = test_world(source);
+        let mut b = test_world(source);
+        let input = InputSnapshot {
+            right: true,
+            ..Default::default()
+        };
+        for _ in 0..2_000 {
+            a.step(&input, DT);
+            b.step(&input, DT);
+        }
+        assert_eq!(a.score, b.score);
+        assert_eq!(a.lives, b.lives);
+        assert_eq!(a.balls.len(), b.balls.len());
+        assert!(a.balls.iter().zip(&b.balls).all(|(a, b)| a.rect == b.rect));
+    }
+}
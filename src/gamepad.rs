@@ -0,0 +1,163 @@
+#[cfg(not(target_arch = "wasm32"))]
+use gilrs::{Axis, Button, EventType, Gilrs};
+
+// How far a stick has to be pushed to count as a direction
+const STICK_THRESHOLD: f32 = 0.5;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum PadButton {
+    Up,
+    Down,
+    Left,
+    Right,
+    Confirm,
+    Back,
+    Fire,
+    Start,
+}
+
+/// Polls connected gamepads once per frame. Gamepads are only supported on
+/// native builds; on wasm nothing is ever pressed.
+pub struct Gamepads {
+    #[cfg(not(target_arch = "wasm32"))]
+    gilrs: Option<Gilrs>,
+    pressed: Vec<PadButton>,
+    released: Vec<PadButton>,
+    held: Vec<PadButton>,
+    stick: (f32, f32),
+}
+
+impl Gamepads {
+    pub fn new() -> Self {
+        Self {
+            // Without gamepad access (e.g. no udev) the game still runs.
+            #[cfg(not(target_arch = "wasm32"))]
+            gilrs: Gilrs::new().ok(),
+            pressed: Vec::new(),
+            released: Vec::new(),
+            held: Vec::new(),
+            stick: (0f32, 0f32),
+        }
+    }
+
+    pub fn update(&mut self) {
+        self.pressed.clear();
+        self.released.clear();
+        self.held.clear();
+        #[cfg(not(target_arch = "wasm32"))]
+        self.poll();
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn poll(&mut self) {
+        let Some(gilrs) = self.gilrs.as_mut() else {
+            return;
+        };
+        while let Some(event) = gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(button, _) => self.pressed.extend(map_button(button)),
+                EventType::ButtonReleased(button, _) => self.released.extend(map_button(button)),
+                _ => {}
+            }
+        }
+
+        let previous = self.stick;
+        self.stick = (0f32, 0f32);
+        for (_, gamepad) in gilrs.gamepads() {
+            for button in MAPPED_BUTTONS {
+                if gamepad.is_pressed(button) {
+                    self.held.extend(map_button(button));
+                }
+            }
+            let (x, y) = (
+                gamepad.value(Axis::LeftStickX),
+                gamepad.value(Axis::LeftStickY),
+            );
+            if x.abs() > self.stick.0.abs() {
+                self.stick.0 = x;
+            }
+            if y.abs() > self.stick.1.abs() {
+                self.stick.1 = y;
+            }
+        }
+
+        // Pushing the stick past the threshold counts as a d-pad press.
+        let directions = [
+            (
+                self.stick.0 < -STICK_THRESHOLD,
+                previous.0 < -STICK_THRESHOLD,
+                PadButton::Left,
+            ),
+            (
+                self.stick.0 > STICK_THRESHOLD,
+                previous.0 > STICK_THRESHOLD,
+                PadButton::Right,
+            ),
+            (
+                self.stick.1 > STICK_THRESHOLD,
+                previous.1 > STICK_THRESHOLD,
+                PadButton::Up,
+            ),
+            (
+                self.stick.1 < -STICK_THRESHOLD,
+                previous.1 < -STICK_THRESHOLD,
+                PadButton::Down,
+            ),
+        ];
+        for (now, before, button) in directions {
+            if now && !before {
+                self.pressed.push(button);
+            }
+            if before && !now {
+                self.released.push(button);
+            }
+            if now {
+                self.held.push(button);
+            }
+        }
+    }
+
+    /// Whether `button` went down this frame on any gamepad.
+    pub fn pressed(&self, button: PadButton) -> bool {
+        self.pressed.contains(&button)
+    }
+
+    /// Whether `button` went up this frame on any gamepad.
+    pub fn released(&self, button: PadButton) -> bool {
+        self.released.contains(&button)
+    }
+
+    /// Whether `button` is down on any gamepad.
+    pub fn held(&self, button: PadButton) -> bool {
+        self.held.contains(&button)
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+const MAPPED_BUTTONS: [Button; 10] = [
+    Button::DPadUp,
+    Button::DPadDown,
+    Button::DPadLeft,
+    Button::DPadRight,
+    Button::South,
+    Button::East,
+    Button::West,
+    Button::RightTrigger,
+    Button::RightTrigger2,
+    Button::Start,
+];
+
+#[cfg(not(target_arch = "wasm32"))]
+fn map_button(button: Button) -> Option<PadButton> {
+    match button {
+        Button::DPadUp => Some(PadButton::Up),
+        Button::DPadDown => Some(PadButton::Down),
+        Button::DPadLeft => Some(PadButton::Left),
+        Button::DPadRight => Some(PadButton::Right),
+        Button::South => Some(PadButton::Confirm),
+        Button::East => Some(PadButton::Back),
+        Button::West | Button::RightTrigger | Button::RightTrigger2 => Some(PadButton::Fire),
+        Button::Start => Some(PadButton::Start),
+        _ => None,
+    }
+}
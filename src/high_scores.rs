@@ -0,0 +1,187 @@
+use crate::storage;
+use macroquad::miniquad;
+
+const STORAGE_KEY: &str = "high_scores";
+const HEADER: &str = "breakdown-scores 1";
+pub const MAX_ENTRIES: usize = 10;
+pub const MAX_NAME_LEN: usize = 12;
+
+#[derive(Clone)]
+pub struct HighScore {
+    pub name: String,
+    pub score: i32,
+    /// Number of the level the run ended on, starting at 1.
+    pub level: usize,
+    /// `YYYY-MM-DD`
+    pub date: String,
+}
+
+/// The best runs, highest score first.
+pub struct HighScores {
+    pub entries: Vec<HighScore>,
+}
+
+impl HighScores {
+    /// Loads the saved table. A missing file gives an empty table and
+    /// damaged lines are skipped, so a corrupt file never stops the game.
+    pub fn load() -> Self {
+        match storage::load(STORAGE_KEY) {
+            Some(text) => Self::parse(&text),
+            None => Self {
+                entries: Vec::new(),
+            },
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        storage::save(STORAGE_KEY, &self.to_text())
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut entries: Vec<HighScore> = text
+            .lines()
+            .filter(|line| line.trim() != HEADER)
+            .filter_map(parse_entry)
+            .collect();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.score));
+        entries.truncate(MAX_ENTRIES);
+        Self { entries }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\n", HEADER);
+        for entry in self.entries.iter() {
+            text += &format!(
+                "{}\t{}\t{}\t{}\n",
+                entry.score, entry.level, entry.date, entry.name
+            );
+        }
+        text
+    }
+
+    /// Whether `score` would make it into the table.
+    pub fn qualifies(&self, score: i32) -> bool {
+        score > 0
+            && (self.entries.len() < MAX_ENTRIES
+                || self.entries.iter().any(|entry| score > entry.score))
+    }
+
+    /// Adds `entry` below any equal scores and returns its position, or
+    /// `None` if it didn't make the cut.
+    pub fn insert(&mut self, entry: HighScore) -> Option<usize> {
+        let position = self
+            .entries
+            .iter()
+            .position(|other| entry.score > other.score)
+            .unwrap_or(self.entries.len());
+        if position >= MAX_ENTRIES {
+            return None;
+        }
+        self.entries.insert(position, entry);
+        self.entries.truncate(MAX_ENTRIES);
+        Some(position)
+    }
+}
+
+fn parse_entry(line: &str) -> Option<HighScore> {
+    let mut fields = line.splitn(4, '\t');
+    let score = fields.next()?.trim().parse().ok()?;
+    let level = fields.next()?.trim().parse().ok()?;
+    let date = fields.next()?.trim().to_string();
+    let name = sanitize_name(fields.next()?);
+    if name.is_empty() {
+        return None;
+    }
+    Some(HighScore {
+        name,
+        score,
+        level,
+        date,
+    })
+}
+
+/// Whether `c` may be part of a player name.
+pub fn is_name_char(c: char) -> bool {
+    c.is_alphanumeric() || c == ' ' || c == '-' || c == '_' || c == '.'
+}
+
+fn sanitize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| is_name_char(*c))
+        .take(MAX_NAME_LEN)
+        .collect::<String>()
+        .trim()
+        .to_string()
+}
+
+/// Today's date as `YYYY-MM-DD` (UTC).
+pub fn today() -> String {
+    let days = (miniquad::date::now() / 86400f64).floor() as i64;
+    // Converts days since 1970-01-01 to a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = match mp < 10 {
+        true => mp + 3,
+        false => mp - 9,
+    };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn damaged_lines_are_skipped_and_entries_sorted() {
+        let mut text = String::from("breakdown-scores 1\n");
+        text += "120\t2\t2026-01-02\tAda\n";
+        text += "\n";
+        text += "not a score line\n";
+        text += "lots\t1\t2026-01-03\tBob\n";
+        text += "900\t3\t2026-01-04\tCleo\n";
+        text += "50\t1\n";
+        text += "70\t1\t2026-01-05\t!!!\n";
+        text += "   \n";
+        text += "300\t2\t2026-01-06\tDan\tO\n";
+        let scores = HighScores::parse(&text);
+        let entries: Vec<(i32, &str)> = scores
+            .entries
+            .iter()
+            .map(|entry| (entry.score, entry.name.as_str()))
+            .collect();
+        assert_eq!(entries, [(900, "Cleo"), (300, "DanO"), (120, "Ada")]);
+        assert_eq!(scores.entries[0].level, 3);
+        assert_eq!(scores.entries[0].date, "2026-01-04");
+    }
+
+    #[test]
+    fn long_lists_are_cut_to_max_entries() {
+        let text: String = (1..=25)
+            .map(|score| format!("{}\t1\t2026-01-01\tP{}\n", score * 10, score))
+            .collect();
+        let scores = HighScores::parse(&text);
+        assert_eq!(scores.entries.len(), MAX_ENTRIES);
+        assert_eq!(scores.entries[0].score, 250);
+        assert_eq!(scores.entries[MAX_ENTRIES - 1].score, 160);
+        assert!(scores
+            .entries
+            .windows(2)
+            .all(|pair| pair[0].score >= pair[1].score));
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let scores = HighScores::parse("500\t2\t2026-03-04\tEve\n40\t1\t2026-03-05\tFay\n");
+        let again = HighScores::parse(&scores.to_text());
+        assert_eq!(again.to_text(), scores.to_text());
+        assert_eq!(again.entries.len(), 2);
+    }
+}
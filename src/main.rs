@@ -1,25 +1,46 @@
-use game::ball::Ball;
-use game::block::{Block, BlockType};
-use game::player::Player;
+use controls::{Action, Controls, KeyBindings};
+use game::block::BlockType;
+use game::events::GameEvent;
+use game::input::{InputSnapshot, PointerControl};
+use game::level::Level;
+use game::particles::Particles;
+use game::replay::{self, Command, Playback, Replay};
 use game::scale::Scale;
-use game::upgrades::{UpgradeCoin, UpgradeType, Upgrades};
+use game::snapshot::Snapshot;
+use game::timestep::{FixedTimestep, DEFAULT_TICK_RATE};
+use game::upgrades::UpgradeType;
+use game::world::{StepOutcome, World};
+use high_scores::{HighScore, HighScores};
 use macroquad::prelude::*;
 use media::audio::GameAudio;
+use settings::{Settings, PARTICLE_LEVELS};
+use ui::{Menu, MenuEvent, Widget};
 
+mod bench;
+mod controls;
 mod game;
+mod gamepad;
+mod high_scores;
 mod media;
+mod settings;
+mod storage;
+mod ui;
 
 const SCALE: f32 = 0.8;
 const SCREEN_SCALE_FACTOR: f32 = 800.0;
-const BLOCK_SIZE: f32 = 40.0;
 const HEADER_POS: Vec2 = Vec2::from_array([5f32, 25f32]);
+// Distance of the active effects strip from the bottom-left corner
+const EFFECTS_HUD_POS: Vec2 = Vec2::from_array([5f32, 28f32]);
+const EFFECT_ICON_SIZE: f32 = 16f32;
 const FONT_SIZE: u16 = 24;
 const TITLE_FONT_SIZE: u16 = 32;
-const PLAYER_SIZE: Vec2 = Vec2::from_array([150f32, 20f32]);
-const PLAYER_SPEED: f32 = 750f32;
-const PLAYER_RELATIVE_POS_Y: f32 = 50f32;
-const BALL_SIZE: f32 = 20f32;
-const BALL_SPEED: f32 = 400f32;
+// Blocks further than this from every ball are hidden while fog is active
+const FOG_RADIUS: f32 = 150f32;
+// Storage key of the run saved on pause and quit
+const SAVED_RUN_KEY: &str = "saved_run";
+// Seconds the level, game over and campaign end screens show before they
+// accept input
+const MIN_SCREEN_TIME: f64 = 0.5;
 
 pub fn draw_title_text(text: &str, font: Font) {
     let dims = measure_text(text, Some(font), TITLE_FONT_SIZE, 1.0);
@@ -36,248 +57,671 @@ pub fn draw_title_text(text: &str, font: Font) {
     );
 }
 
+#[derive(Clone, Copy, PartialEq)]
 pub enum GameState {
     Menu,
     Game,
     LaunchNewBall,
+    Paused,
+    Settings,
+    LevelSelect,
+    HighScores,
+    Controls,
+    EnterName,
+    Replay,
     LevelCompleted,
+    CampaignCompleted,
     GameOver,
 }
 
 struct Game {
     state: GameState,
-    player: Player,
-    balls: Vec<Ball>,
-    blocks: Vec<Block>,
+    /// Time the current state was entered.
+    state_entered: f64,
+    world: World,
     font: Font,
-    score: i32,
-    lives: i32,
     audio: GameAudio,
-    scale: Scale,
-    upgrades: Upgrades,
+    levels: Vec<Level>,
+    level_index: usize,
+    timestep: FixedTimestep,
+    particles: Particles,
+    settings: Settings,
+    /// State to go back to when leaving the pause menu or the settings.
+    paused_from: GameState,
+    settings_from: GameState,
+    main_menu: Menu,
+    pause_menu: Menu,
+    settings_menu: Menu,
+    level_menu: Menu,
+    high_scores_menu: Menu,
+    controls_menu: Menu,
+    controls: Controls,
+    /// Action waiting for a new key on the controls screen.
+    rebinding: Option<Action>,
+    /// Gameplay input read at the start of the frame.
+    input: InputSnapshot,
+    selected_level: usize,
+    /// Whether a run was left through the pause menu and can be picked up again.
+    can_continue: bool,
+    /// Score and lives when the current level started, for restarting it.
+    checkpoint: (i32, i32),
+    high_scores: HighScores,
+    /// Name typed on the name entry screen, kept as the default for the next one.
+    player_name: String,
+    /// Table position of the score entered last, highlighted when shown.
+    new_high_score: Option<usize>,
+    /// Game over or campaign completed, shown after the name entry.
+    run_end_state: GameState,
+    /// Where to write the replay of each run, if recording.
+    record_path: Option<String>,
+    recording: Option<Replay>,
+    playback: Option<Playback>,
+    quit: bool,
 }
 
-impl<'a> Game {
-    fn generate_blocks() -> Vec<Block> {
-        let mut blocks = Vec::new();
-        let (width, height) = (15, 6);
-        let padding = 5f32;
-        let screen_scale: f32 = screen_width() / SCREEN_SCALE_FACTOR;
-        let block_size: f32 = BLOCK_SIZE * SCALE * screen_scale;
-        let total_block_size = vec2(block_size, block_size) + vec2(padding, padding);
-        let board_start_pos = vec2(
-            (screen_width() - (total_block_size.x * width as f32)) * 0.5f32,
-            50f32,
-        );
+fn screen_bounds() -> Rect {
+    Rect::new(0f32, 0f32, screen_width(), screen_height())
+}
+
+fn new_seed() -> u64 {
+    (miniquad::date::now() * 1000f64) as u64
+}
+
+impl Game {
+    pub async fn new(scale: Scale, levels: Vec<Level>) -> Self {
+        let checkpoint = (0, levels[0].lives);
+        let mut game = Self {
+            state: GameState::Menu,
+            state_entered: get_time(),
+            world: World::new(screen_bounds(), scale, &levels[0], new_seed()),
+            font: load_ttf_font_from_bytes(include_bytes!("../res/Roboto-Regular.ttf")).unwrap(),
+            audio: GameAudio::new().await,
+            levels,
+            level_index: 0,
+            timestep: FixedTimestep::new(DEFAULT_TICK_RATE),
+            particles: Particles::new(new_seed()),
+            settings: Settings::load(),
+            paused_from: GameState::Game,
+            settings_from: GameState::Menu,
+            main_menu: Menu::new(),
+            pause_menu: Menu::new(),
+            settings_menu: Menu::new(),
+            level_menu: Menu::new(),
+            high_scores_menu: Menu::new(),
+            controls_menu: Menu::new(),
+            controls: Controls::new(),
+            rebinding: None,
+            input: InputSnapshot::default(),
+            selected_level: 0,
+            can_continue: false,
+            checkpoint,
+            high_scores: HighScores::load(),
+            player_name: String::new(),
+            new_high_score: None,
+            run_end_state: GameState::GameOver,
+            record_path: None,
+            recording: None,
+            playback: None,
+            quit: false,
+        };
+        game.load_run();
+        game.timestep = FixedTimestep::new(game.settings.tick_rate);
+        game.apply_settings();
+        game
+    }
 
-        for i in 0..width * height {
-            let block_x = (i % width) as f32 * total_block_size.x;
-            let block_y = (i / width) as f32 * total_block_size.y;
-            blocks.push(Block::new(
-                board_start_pos + vec2(block_x, block_y),
-                BlockType::Regular,
-                block_size,
+    fn level(&self) -> &Level {
+        &self.levels[self.level_index]
+    }
+
+    /// Starts a new run on the level at `level_index`.
+    pub fn new_run(&mut self, level_index: usize) {
+        // A run left through the menu is still being recorded.
+        self.write_replay();
+        self.level_index = level_index;
+        self.can_continue = false;
+        storage::remove(SAVED_RUN_KEY);
+        let seed = new_seed();
+        self.world.resize(screen_bounds());
+        self.world.reset(&self.levels[self.level_index], seed);
+        self.particles.clear();
+        self.checkpoint = (self.world.score, self.world.lives);
+        if self.record_path.is_some() {
+            self.recording = Some(Replay::new(
+                seed,
+                level_index,
+                self.world.bounds,
+                self.world.auto_fire,
+                self.settings.tick_rate,
             ));
+            self.record(Command::PointerControl(self.world.pointer_control));
+        }
+    }
+
+    fn next_level(&mut self) {
+        self.level_index += 1;
+        self.world.resize(screen_bounds());
+        self.world.load_level(&self.levels[self.level_index]);
+        self.particles.clear();
+        self.checkpoint = (self.world.score, self.world.lives);
+        self.record(Command::NextLevel(self.world.bounds));
+    }
+
+    fn restart_level(&mut self) {
+        let (score, lives) = self.checkpoint;
+        self.world.resize(screen_bounds());
+        self.world
+            .restart_level(&self.levels[self.level_index], score, lives);
+        self.particles.clear();
+        self.record(Command::RestartLevel(self.world.bounds));
+    }
+
+    fn record(&mut self, command: Command) {
+        if let Some(recording) = self.recording.as_mut() {
+            recording.record(command);
+        }
+    }
+
+    /// Writes the replay of the current run with its final score and state
+    /// hash, and stops recording.
+    fn write_replay(&mut self) {
+        let (Some(mut recording), Some(path)) = (self.recording.take(), self.record_path.as_ref())
+        else {
+            return;
+        };
+        let hash = replay::state_hash(&self.world, self.level_index, self.level(), self.checkpoint);
+        recording.result = Some((self.world.score, hash));
+        match std::fs::write(path, recording.to_text()) {
+            Ok(()) => println!("Replay written to {}", path),
+            Err(error) => eprintln!("Could not write the replay to {}: {}", path, error),
+        }
+    }
+
+    /// Plays a recorded run back on screen.
+    fn start_replay(&mut self, replay: &Replay) {
+        match Playback::start(replay, &mut self.world, &self.levels) {
+            Ok(playback) => {
+                self.level_index = playback.level_index;
+                self.playback = Some(playback);
+                self.timestep = FixedTimestep::new(replay.tick_rate);
+                // The world no longer holds the saved run.
+                self.can_continue = false;
+                self.particles.clear();
+                self.state = GameState::Replay;
+            }
+            Err(error) => eprintln!("Could not play the replay: {}", error),
         }
+    }
 
-        // TODO: Remove this after implementing the new upgrades
-        for _ in 0..53 {
-            let rand_index = rand::gen_range(0, blocks.len());
-            blocks[rand_index].block_type = BlockType::Upgrade;
+    /// Called when the run ends in `self.state`; asks for a name first if the
+    /// score made it into the high score table.
+    fn finish_run(&mut self) {
+        self.can_continue = false;
+        storage::remove(SAVED_RUN_KEY);
+        self.write_replay();
+        self.new_high_score = None;
+        self.run_end_state = self.state;
+        if self.high_scores.qualifies(self.world.score) {
+            // Drop keys typed during play so they don't end up in the name.
+            while get_char_pressed().is_some() {}
+            self.state = GameState::EnterName;
         }
+    }
 
-        return blocks;
+    /// Whether there is a run that quitting now would interrupt.
+    fn run_in_progress(&self) -> bool {
+        match self.state {
+            GameState::Game
+            | GameState::LaunchNewBall
+            | GameState::Paused
+            | GameState::LevelCompleted => true,
+            GameState::Settings => self.settings_from == GameState::Paused,
+            _ => self.can_continue,
+        }
     }
 
-    pub async fn new(scale: Scale) -> Self {
-        let ball_position = vec2(
-            screen_width() * 0.5f32,
-            screen_height() - PLAYER_RELATIVE_POS_Y - PLAYER_SIZE.y,
+    fn save_run(&self) {
+        let snapshot = Snapshot::capture(
+            &self.world,
+            self.level_index,
+            &self.level().name,
+            self.checkpoint,
         );
-        Self {
-            state: GameState::Menu,
-            player: Player::new(PLAYER_SIZE, PLAYER_SPEED, PLAYER_RELATIVE_POS_Y, scale),
-            balls: vec![Ball::new(
-                ball_position,
-                BALL_SIZE * scale.total_scale,
-                BALL_SPEED * scale.total_scale,
-            )],
-            blocks: Game::generate_blocks(),
-            font: load_ttf_font_from_bytes(include_bytes!("../res/Roboto-Regular.ttf")).unwrap(),
-            score: 0,
-            lives: 3,
-            audio: GameAudio::new().await,
-            scale,
-            upgrades: Upgrades::new(),
+        if let Err(error) = storage::save(SAVED_RUN_KEY, &snapshot.to_text()) {
+            eprintln!("Could not save the run: {}", error);
         }
     }
 
-    fn new_ball_next_to_player(&self) -> Ball {
-        let ball_position = self.player.rect.point()
-            + vec2(
-                self.player.rect.w * 0.5f32 - BALL_SIZE * 0.5f32,
-                -PLAYER_SIZE.y,
-            );
-        Ball::new(
-            ball_position,
-            BALL_SIZE * self.scale.total_scale,
-            BALL_SPEED * self.scale.total_scale,
-        )
+    /// Picks up the run saved when the game was last quit, offering it as
+    /// "Continue" in the main menu. Saves that don't fit are ignored.
+    fn load_run(&mut self) {
+        let Some(text) = storage::load(SAVED_RUN_KEY) else {
+            return;
+        };
+        let snapshot = match Snapshot::parse(&text) {
+            Ok(snapshot) => snapshot,
+            Err(error) => {
+                eprintln!("Ignoring the saved run: {}", error);
+                return;
+            }
+        };
+        let level_index = snapshot.level_index;
+        match self.levels.get(level_index) {
+            Some(level) if level.name == snapshot.level_name => {}
+            _ => {
+                eprintln!(
+                    "Ignoring the saved run: level {} `{}` doesn't exist",
+                    level_index + 1,
+                    snapshot.level_name
+                );
+                return;
+            }
+        }
+        self.level_index = level_index;
+        self.checkpoint = snapshot.checkpoint;
+        snapshot.restore(&mut self.world, &self.levels[level_index]);
+        self.paused_from = match self.world.balls.is_empty() {
+            true => GameState::LaunchNewBall,
+            false => GameState::Game,
+        };
+        self.can_continue = true;
     }
 
-    pub fn spawn_ball_next_to_player(&mut self) {
-        self.balls.push(self.new_ball_next_to_player());
+    /// Saves the run in progress before the game closes.
+    pub fn exit(&mut self) {
+        if self.run_in_progress() {
+            self.save_run();
+        }
+        self.write_replay();
     }
 
-    pub fn new_super_ball_next_to_player(&self) -> Ball {
-        let ball_position = self.player.rect.point()
-            + vec2(
-                self.player.rect.w * 0.5f32 - BALL_SIZE * 0.5f32,
-                -PLAYER_SIZE.y,
-            );
-        Ball::new_super_ball(
-            ball_position,
-            BALL_SIZE * self.scale.total_scale,
-            BALL_SPEED * self.scale.total_scale,
-        )
+    fn apply_settings(&mut self) {
+        self.audio.volume = self.settings.volume;
+        if self.world.auto_fire != self.settings.auto_fire {
+            self.world.auto_fire = self.settings.auto_fire;
+            self.record(Command::AutoFire(self.world.auto_fire));
+        }
+        let pointer_control = PointerControl {
+            smoothing: self.settings.pointer_smoothing,
+            max_speed: self.settings.pointer_speed,
+        };
+        if self.world.pointer_control != pointer_control {
+            self.world.pointer_control = pointer_control;
+            self.record(Command::PointerControl(pointer_control));
+        }
+        self.particles.density = self.settings.particle_density();
+        self.controls.bindings = self.settings.bindings.clone();
     }
 
-    pub fn spawn_ball(&mut self, point: Vec2) {
-        self.balls.push(Ball::new(
-            point,
-            BALL_SIZE * self.scale.total_scale,
-            BALL_SPEED * self.scale.total_scale,
-        ));
+    fn save_settings(&self) {
+        if let Err(error) = self.settings.save() {
+            eprintln!("Could not save the settings: {}", error);
+        }
     }
 
-    pub fn reset(&mut self) {
-        self.score = 0;
-        self.lives = 3;
-        self.scale.update();
-        self.player.rect.x = screen_width() * 0.5f32 - PLAYER_SIZE.x * 0.5f32;
-        self.balls = vec![self.new_ball_next_to_player()];
-        self.blocks = Game::generate_blocks();
-        self.scale.update();
-        self.upgrades = Upgrades::new();
+    /// The keys bound to `action`, for prompts.
+    fn keys_for(&self, action: Action) -> String {
+        self.settings.bindings.describe(action).to_uppercase()
     }
 
-    fn state_menu(&mut self) {
-        draw_title_text("Press SPACE to start", self.font);
-        if is_key_down(KeyCode::Space) {
-            self.state = GameState::Game;
+    fn open_settings(&mut self) {
+        self.settings_from = self.state;
+        self.settings_menu.selected = 0;
+        self.state = GameState::Settings;
+    }
+
+    fn pause(&mut self) {
+        self.save_run();
+        self.paused_from = self.state;
+        self.state = GameState::Paused;
+        self.pause_menu.selected = 0;
+    }
+
+    /// Hands the events published by the simulation to the frontend systems.
+    fn handle_events(&mut self) {
+        let events: Vec<GameEvent> = self.world.events.drain_published().collect();
+        for event in events.iter() {
+            self.audio_system(event);
+            self.particle_system(event);
         }
     }
 
-    fn state_game(&mut self) {
-        self.player.update(get_frame_time());
-        for ball in self.balls.iter_mut() {
-            ball.update(get_frame_time());
-        }
-
-        let mut spawn_later = Vec::new();
-        for ball in self.balls.iter_mut() {
-            if ball.bounce(&self.player.rect) {
-                self.audio.play_single(self.audio.hit_player);
-            }
-            for block in self.blocks.iter_mut() {
-                if ball.bounce(&block.rect) {
-                    self.audio.play_single(self.audio.hit_block);
-                    if ball.super_ball {
-                        block.lives = 0;
-                    } else {
-                        block.lives -= 1;
-                    }
-                    if block.lives <= 0 {
-                        self.score += 10;
-                        if block.block_type == BlockType::Upgrade {
-                            self.upgrades.spawn_upgrade(block.rect);
-                        }
-                    }
-                }
+    fn audio_system(&mut self, event: &GameEvent) {
+        let sound = match event {
+            GameEvent::BlockHit { .. } | GameEvent::Explosion { .. } => self.audio.hit_block,
+            GameEvent::PaddleHit => self.audio.hit_player,
+            GameEvent::LifeLost => self.audio.hit_floor,
+            GameEvent::ProjectileFired => self.audio.shoot,
+            _ => return,
+        };
+        self.audio.play_single(sound);
+    }
+
+    fn particle_system(&mut self, event: &GameEvent) {
+        let scale = self.world.scale.total_scale;
+        let size = 6f32 * scale;
+        let speed = 250f32 * scale;
+        match *event {
+            GameEvent::BlockDestroyed {
+                rect, block_type, ..
+            } => {
+                let color = match block_type {
+                    BlockType::Regular => RED,
+                    BlockType::Upgrade => GREEN,
+                    BlockType::Steel => GRAY,
+                    BlockType::Explosive => YELLOW,
+                    BlockType::Moving => DARKBLUE,
+                };
+                self.particles.burst(rect.center(), color, 12, speed, size);
+            }
+            GameEvent::Explosion { center, radius } => {
+                self.particles
+                    .burst(center, ORANGE, 40, radius * 3f32, size * 1.5f32);
+            }
+            GameEvent::UpgradeCollected { upgrade_type, rect } => {
+                self.particles
+                    .burst(rect.center(), upgrade_type.color(), 10, speed, size);
+            }
+            GameEvent::BallLost { rect } => {
+                self.particles
+                    .burst(rect.center(), DARKGRAY, 8, speed * 0.5f32, size);
             }
+            GameEvent::LifeGained => {
+                let paddle = self.world.player.rect;
+                self.particles.burst(paddle.center(), GOLD, 20, speed, size);
+            }
+            _ => {}
         }
+    }
 
-        for point in spawn_later.into_iter() {
-            self.spawn_ball(point);
+    fn draw_screen_title(&self, title: &str) {
+        let title_size = (TITLE_FONT_SIZE as f32 * self.world.scale.total_scale * 1.5f32) as u16;
+        ui::draw_centered_text(
+            title,
+            screen_height() * 0.25f32,
+            self.font,
+            title_size,
+            BLACK,
+        );
+    }
+
+    fn state_menu(&mut self) {
+        self.draw_screen_title("BREAKDOWN");
+        let mut widgets = [
+            Widget::button("New Game"),
+            Widget::Button {
+                label: String::from("Continue"),
+                enabled: self.can_continue,
+            },
+            Widget::button("Level Select"),
+            Widget::button("Settings"),
+            Widget::button("High Scores"),
+            Widget::button("Quit"),
+        ];
+        let event = self.main_menu.update(
+            &mut widgets,
+            screen_height() * 0.4f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        match event {
+            Some(MenuEvent::Pressed(0)) => {
+                self.new_run(0);
+                self.state = GameState::Game;
+            }
+            Some(MenuEvent::Pressed(1)) => {
+                self.pause_menu.selected = 0;
+                self.state = GameState::Paused;
+            }
+            Some(MenuEvent::Pressed(2)) => {
+                self.level_menu.selected = 0;
+                self.state = GameState::LevelSelect;
+            }
+            Some(MenuEvent::Pressed(3)) => self.open_settings(),
+            Some(MenuEvent::Pressed(4)) => {
+                self.high_scores_menu.selected = 0;
+                self.state = GameState::HighScores;
+            }
+            Some(MenuEvent::Pressed(5) | MenuEvent::Back) => self.quit = true,
+            _ => {}
         }
+    }
 
-        let balls_len = self.balls.len();
-        // Remove balls below the screen
-        self.balls.retain(|ball| ball.rect.y < screen_height());
+    fn state_level_select(&mut self) {
+        self.draw_screen_title("LEVEL SELECT");
+        let mut widgets = [
+            Widget::Selector {
+                label: String::from("Level"),
+                options: self
+                    .levels
+                    .iter()
+                    .enumerate()
+                    .map(|(index, level)| format!("{}. {}", index + 1, level.name))
+                    .collect(),
+                index: self.selected_level,
+            },
+            Widget::button("Start"),
+            Widget::button("Back"),
+        ];
+        let event = self.level_menu.update(
+            &mut widgets,
+            screen_height() * 0.4f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        if let Widget::Selector { index, .. } = widgets[0] {
+            self.selected_level = index;
+        }
+        match event {
+            Some(MenuEvent::Pressed(1)) => {
+                self.new_run(self.selected_level);
+                self.state = GameState::Game;
+            }
+            Some(MenuEvent::Pressed(2) | MenuEvent::Back) => self.state = GameState::Menu,
+            _ => {}
+        }
+    }
 
-        let removed_balls = balls_len - self.balls.len();
-        if removed_balls > 0 && self.balls.is_empty() {
-            self.lives -= 1;
-            self.audio.play_single(self.audio.hit_floor);
-            self.state = GameState::LaunchNewBall;
+    fn state_high_scores(&mut self) {
+        self.draw_screen_title("HIGH SCORES");
+        self.draw_high_scores(screen_height() * 0.35f32);
+        let mut widgets = [Widget::button("Back")];
+        let event = self.high_scores_menu.update(
+            &mut widgets,
+            screen_height() * 0.8f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        if let Some(MenuEvent::Pressed(0) | MenuEvent::Back) = event {
+            self.state = GameState::Menu;
+        }
+    }
 
-            if self.lives <= 0 {
-                self.state = GameState::GameOver;
+    /// Draws the high score table with its first row at `top`, highlighting
+    /// the score that was just entered.
+    fn draw_high_scores(&self, top: f32) {
+        let font_size = self.font_size();
+        if self.high_scores.entries.is_empty() {
+            ui::draw_centered_text("No high scores yet", top, self.font, font_size, GRAY);
+            return;
+        }
+        // Column positions relative to the screen center, in font sizes
+        let columns = [-10.5f32, -9f32, -1.5f32, 2.5f32, 5f32];
+        let line_height = font_size as f32 * 1.2f32;
+        let center = screen_width() * 0.5f32;
+        let header = ["#", "Name", "Score", "Level", "Date"];
+        let rows = self
+            .high_scores
+            .entries
+            .iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                (
+                    [
+                        format!("{}.", index + 1),
+                        entry.name.clone(),
+                        entry.score.to_string(),
+                        entry.level.to_string(),
+                        entry.date.clone(),
+                    ],
+                    match self.new_high_score == Some(index) {
+                        true => RED,
+                        false => BLACK,
+                    },
+                )
+            });
+        let header = (header.map(String::from), GRAY);
+        for (row, (cells, color)) in std::iter::once(header).chain(rows).enumerate() {
+            for (cell, column) in cells.iter().zip(columns) {
+                draw_text_ex(
+                    cell,
+                    center + column * font_size as f32,
+                    top + row as f32 * line_height,
+                    TextParams {
+                        font: self.font,
+                        font_size,
+                        color,
+                        ..Default::default()
+                    },
+                );
             }
-            self.upgrades.reset()
         }
+    }
 
-        self.blocks.retain(|block| block.lives > 0);
-        if self.blocks.is_empty() {
-            self.state = GameState::LevelCompleted;
+    fn state_enter_name(&mut self) {
+        self.draw_screen_title("NEW HIGH SCORE");
+        let font_size = self.font_size();
+        let line_height = font_size as f32 * 1.6f32;
+        let top = screen_height() * 0.4f32;
+        ui::draw_centered_text(
+            &format!("Score: {}", self.world.score),
+            top,
+            self.font,
+            font_size,
+            BLACK,
+        );
+        ui::draw_centered_text(
+            "Enter your name:",
+            top + line_height,
+            self.font,
+            font_size,
+            BLACK,
+        );
+        while let Some(c) = get_char_pressed() {
+            if high_scores::is_name_char(c)
+                && self.player_name.chars().count() < high_scores::MAX_NAME_LEN
+            {
+                self.player_name.push(c);
+            }
         }
+        if is_key_pressed(KeyCode::Backspace) {
+            self.player_name.pop();
+        }
+        // Blinking cursor
+        let cursor = match get_time().fract() < 0.5 {
+            true => "_",
+            false => " ",
+        };
+        ui::draw_centered_text(
+            &format!("{}{}", self.player_name, cursor),
+            top + line_height * 2f32,
+            self.font,
+            font_size,
+            RED,
+        );
+        ui::draw_centered_text(
+            &format!("Press {} to confirm", self.keys_for(Action::Confirm)),
+            top + line_height * 4f32,
+            self.font,
+            font_size,
+            GRAY,
+        );
 
-        self.upgrades.update(self.player.rect);
-        self.activate_upgrades();
-        self.draw_game();
+        if self.controls.pressed(Action::Confirm) {
+            self.player_name = self.player_name.trim().to_string();
+            let name = match self.player_name.is_empty() {
+                true => String::from("Player"),
+                false => self.player_name.clone(),
+            };
+            self.new_high_score = self.high_scores.insert(HighScore {
+                name,
+                score: self.world.score,
+                level: self.level_index + 1,
+                date: high_scores::today(),
+            });
+            if let Err(error) = self.high_scores.save() {
+                eprintln!("Could not save the high scores: {}", error);
+            }
+            self.state = self.run_end_state;
+        }
     }
 
-    fn activate_upgrades(&'a mut self) {
-        for upgrade in self.upgrades.active_upgrades.iter() {
-            match upgrade {
-                UpgradeType::AddBall => {
-                    self.balls.push(self.new_ball_next_to_player());
-                }
-                UpgradeType::ExtraLife => {
-                    self.lives += 1;
-                }
-                UpgradeType::SuperBall => {
-                    self.balls.push(self.new_super_ball_next_to_player());
-                }
-                UpgradeType::BallMultiplier => {
-                    let mut new_balls: Vec<Ball> = vec![];
-                    for ball in self.balls.iter() {
-                        let mut new_ball: Ball = match ball. super_ball{
-                            true => Ball::new_super_ball(ball.rect.point(), ball.rect.w, ball.speed),
-                            false => Ball::new(ball.rect.point(), ball.rect.w, ball.speed),
-                        };
-                        new_ball.random_direction();
-                        new_balls.push(new_ball);
-                    }
-                    for ball in new_balls {
-                        self.balls.push(ball);
-                    }
-                },
-                UpgradeType::SpaceInvader => {
-                    self.upgrades.space_invader_active = true;
-                },
-                UpgradeType::Magnet => {
-                    self.upgrades.magnet_active = true;
+    fn state_game(&mut self) {
+        if self.controls.pressed(Action::Pause) {
+            self.pause();
+            return self.draw_game();
+        }
+        for _ in 0..self.timestep.advance(get_frame_time()) {
+            self.record(Command::Step(self.input));
+            let outcome = self.world.step(&self.input, self.timestep.dt);
+            // A press only counts for the first step that sees it.
+            self.input.launch_pressed = false;
+            self.state = match outcome {
+                StepOutcome::Playing => continue,
+                StepOutcome::LifeLost => GameState::LaunchNewBall,
+                StepOutcome::GameOver => GameState::GameOver,
+                StepOutcome::LevelCleared => match self.level_index + 1 < self.levels.len() {
+                    true => GameState::LevelCompleted,
+                    false => GameState::CampaignCompleted,
                 },
-                _ => {
-                    self.balls.push(self.new_ball_next_to_player());
-                }
-            }
+            };
+            self.timestep.reset();
+            break;
         }
-        self.upgrades.active_upgrades.clear();
+        if matches!(
+            self.state,
+            GameState::GameOver | GameState::CampaignCompleted
+        ) {
+            self.finish_run();
+        }
+        self.handle_events();
+        self.particles.update(get_frame_time());
+        self.draw_game();
     }
 
     fn draw_game(&mut self) {
-        self.player.draw();
-        for block in self.blocks.iter() {
-            block.draw();
+        let alpha = self.timestep.alpha();
+        self.world.player.draw(alpha);
+        if self.world.upgrades.is_active(UpgradeType::SpaceInvader) {
+            self.world.player.draw_cannons(alpha);
         }
-        for ball in self.balls.iter() {
-            ball.draw();
+        let fog = self.world.upgrades.is_active(UpgradeType::Fog);
+        let fog_radius = FOG_RADIUS * self.world.scale.total_scale;
+        for block in self.world.blocks.iter() {
+            let visible = !fog
+                || self
+                    .world
+                    .balls
+                    .iter()
+                    .any(|ball| ball.rect.center().distance(block.rect.center()) < fog_radius);
+            if visible {
+                block.draw(alpha);
+            }
         }
-        self.upgrades.draw();
-        let score_text = format!("score: {}", self.score);
-        let final_font_size = (FONT_SIZE as f32 * self.scale.total_scale) as u16;
+        for ball in self.world.balls.iter() {
+            ball.draw(alpha);
+        }
+        for projectile in self.world.projectiles.iter() {
+            projectile.draw(alpha);
+        }
+        self.world.upgrades.draw(alpha);
+        self.particles.draw();
+        let total_scale = self.world.scale.total_scale;
+        let score_text = format!("score: {}", self.world.score);
+        let final_font_size = (FONT_SIZE as f32 * total_scale) as u16;
         let score_text_dim = measure_text(&score_text, Some(self.font), final_font_size, 1.0);
         let text_params = TextParams {
             font: self.font,
@@ -288,44 +732,369 @@ impl<'a> Game {
         draw_text_ex(
             &score_text,
             screen_width() * 0.5f32 - score_text_dim.width * 0.5f32,
-            HEADER_POS.y * self.scale.total_scale,
+            HEADER_POS.y * total_scale,
             text_params,
         );
 
         draw_text_ex(
-            &format!("lives: {}", self.lives),
+            &format!("lives: {}", self.world.lives),
             HEADER_POS.x,
-            HEADER_POS.y * self.scale.total_scale,
+            HEADER_POS.y * total_scale,
             text_params,
         );
+
+        let level_text = format!("level {}: {}", self.level_index + 1, self.level().name);
+        let level_text_dim = measure_text(&level_text, Some(self.font), final_font_size, 1.0);
+        draw_text_ex(
+            &level_text,
+            screen_width() - level_text_dim.width - HEADER_POS.x,
+            HEADER_POS.y * total_scale,
+            text_params,
+        );
+
+        let balls_text = format!("balls: {}/{}", self.world.balls.len(), self.world.max_balls);
+        let balls_text_dim = measure_text(&balls_text, Some(self.font), final_font_size, 1.0);
+        draw_text_ex(
+            &balls_text,
+            screen_width() - balls_text_dim.width - HEADER_POS.x,
+            screen_height() - (EFFECTS_HUD_POS.y - EFFECT_ICON_SIZE) * total_scale,
+            TextParams {
+                color: match self.world.balls.len() >= self.world.max_balls {
+                    true => RED,
+                    false => BLACK,
+                },
+                ..text_params
+            },
+        );
+
+        self.world.upgrades.draw_effects(
+            vec2(
+                EFFECTS_HUD_POS.x,
+                screen_height() - EFFECTS_HUD_POS.y * total_scale,
+            ),
+            EFFECT_ICON_SIZE * total_scale,
+        );
     }
 
     fn state_launch_new_ball(&mut self) {
-        self.player.update(get_frame_time());
-        if is_key_down(KeyCode::Space) {
+        if self.controls.pressed(Action::Pause) {
+            self.pause();
+            return self.draw_game();
+        }
+        let input = self.input;
+        for _ in 0..self.timestep.advance(get_frame_time()) {
+            self.record(Command::MovePlayer(input));
+            self.world.update_player(&input, self.timestep.dt);
+        }
+        // Launch input held since the ball was lost doesn't count.
+        if input.launch_pressed {
             self.state = GameState::Game;
-            self.spawn_ball_next_to_player();
+            self.record(Command::Launch);
+            self.world.spawn_ball_next_to_player();
         }
+        self.particles.update(get_frame_time());
         self.draw_game();
     }
 
-    fn state_level_completed(&mut self) {
-        draw_title_text("You WIN!", self.font);
-        if is_key_down(KeyCode::Space) {
+    fn state_replay(&mut self) {
+        if self.controls.pressed(Action::Back) {
+            self.playback = None;
+            self.main_menu.selected = 0;
+            self.timestep = FixedTimestep::new(self.settings.tick_rate);
+            self.state = GameState::Menu;
+            return;
+        }
+        let Some(playback) = self.playback.as_mut() else {
             self.state = GameState::Menu;
-            self.reset();
+            return;
+        };
+        for _ in 0..self.timestep.advance(get_frame_time()) {
+            if playback
+                .tick(&mut self.world, &self.levels, self.timestep.dt)
+                .is_none()
+            {
+                break;
+            }
+        }
+        self.level_index = playback.level_index;
+        let finished = playback.is_finished();
+        self.handle_events();
+        self.particles.update(get_frame_time());
+        self.draw_game();
+        let text = match finished {
+            true => format!("REPLAY FINISHED - press {}", self.keys_for(Action::Back)),
+            false => format!("REPLAY - press {} to stop", self.keys_for(Action::Back)),
+        };
+        ui::draw_centered_text(
+            &text,
+            screen_height() * 0.5f32,
+            self.font,
+            self.font_size(),
+            GRAY,
+        );
+    }
+
+    fn font_size(&self) -> u16 {
+        (FONT_SIZE as f32 * self.world.scale.total_scale) as u16
+    }
+
+    fn state_paused(&mut self) {
+        self.draw_game();
+        ui::dim_screen();
+        self.draw_screen_title("PAUSED");
+        let mut widgets = [
+            Widget::button("Resume"),
+            Widget::button("Restart level"),
+            Widget::button("Settings"),
+            Widget::button("Quit to menu"),
+        ];
+        let event = self.pause_menu.update(
+            &mut widgets,
+            screen_height() * 0.4f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        if self.controls.pressed(Action::Pause) {
+            self.state = self.paused_from;
+            return;
+        }
+        match event {
+            Some(MenuEvent::Pressed(0) | MenuEvent::Back) => self.state = self.paused_from,
+            Some(MenuEvent::Pressed(1)) => {
+                self.restart_level();
+                self.state = GameState::LaunchNewBall;
+            }
+            Some(MenuEvent::Pressed(2)) => self.open_settings(),
+            Some(MenuEvent::Pressed(3)) => {
+                self.can_continue = true;
+                self.main_menu.selected = 0;
+                self.state = GameState::Menu;
+            }
+            _ => {}
         }
     }
 
+    fn state_settings(&mut self) {
+        if self.settings_from == GameState::Paused {
+            self.draw_game();
+            ui::dim_screen();
+        }
+        self.draw_screen_title("SETTINGS");
+        let mut widgets = [
+            Widget::Slider {
+                label: String::from("Volume"),
+                value: self.settings.volume,
+                min: 0f32,
+                max: 1f32,
+                step: 0.1,
+            },
+            Widget::Toggle {
+                label: String::from("Auto fire"),
+                value: self.settings.auto_fire,
+            },
+            Widget::Slider {
+                label: String::from("Pointer smoothing"),
+                value: self.settings.pointer_smoothing,
+                min: 0f32,
+                max: 0.2,
+                step: 0.02,
+            },
+            Widget::Slider {
+                label: String::from("Pointer speed"),
+                value: self.settings.pointer_speed,
+                min: 1f32,
+                max: 4f32,
+                step: 0.25,
+            },
+            Widget::Selector {
+                label: String::from("Particles"),
+                options: PARTICLE_LEVELS
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect(),
+                index: self.settings.particle_level,
+            },
+            Widget::button("Controls"),
+            Widget::button("Back"),
+        ];
+        let event = self.settings_menu.update(
+            &mut widgets,
+            screen_height() * 0.4f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        if let Widget::Slider { value, .. } = widgets[0] {
+            self.settings.volume = value;
+        }
+        if let Widget::Toggle { value, .. } = widgets[1] {
+            self.settings.auto_fire = value;
+        }
+        if let Widget::Slider { value, .. } = widgets[2] {
+            self.settings.pointer_smoothing = value;
+        }
+        if let Widget::Slider { value, .. } = widgets[3] {
+            self.settings.pointer_speed = value;
+        }
+        if let Widget::Selector { index, .. } = widgets[4] {
+            self.settings.particle_level = index;
+        }
+        self.apply_settings();
+        match event {
+            Some(MenuEvent::Pressed(5)) => {
+                self.controls_menu.selected = 0;
+                self.state = GameState::Controls;
+            }
+            Some(MenuEvent::Pressed(6) | MenuEvent::Back) => {
+                self.save_settings();
+                self.state = self.settings_from;
+            }
+            _ => {}
+        }
+    }
+
+    /// Lists the keys bound to each action; picking one waits for a new key.
+    fn state_controls(&mut self) {
+        if self.settings_from == GameState::Paused {
+            self.draw_game();
+            ui::dim_screen();
+        }
+        self.draw_screen_title("CONTROLS");
+        if let Some(action) = self.rebinding {
+            ui::draw_centered_text(
+                &format!(
+                    "Press a key for {} ({} cancels)",
+                    action.label(),
+                    self.keys_for(Action::Back)
+                ),
+                screen_height() * 0.4f32,
+                self.font,
+                self.font_size(),
+                BLACK,
+            );
+            // Cancelling goes through the Back action, so Escape can be bound
+            // once Back uses another key.
+            if self.controls.pressed(Action::Back) {
+                self.rebinding = None;
+                return;
+            }
+            if let Some(key) = get_last_key_pressed() {
+                if controls::BINDABLE_KEYS.contains(&key) {
+                    self.settings.bindings.set(action, vec![key]);
+                    self.apply_settings();
+                    self.save_settings();
+                    self.rebinding = None;
+                }
+            }
+            return;
+        }
+
+        let mut widgets: Vec<Widget> = Action::ALL
+            .iter()
+            .map(|action| {
+                Widget::button(&format!(
+                    "{}: {}",
+                    action.label(),
+                    self.settings.bindings.describe(*action)
+                ))
+            })
+            .collect();
+        widgets.push(Widget::button("Reset to defaults"));
+        widgets.push(Widget::button("Back"));
+        let event = self.controls_menu.update(
+            &mut widgets,
+            screen_height() * 0.35f32,
+            self.font,
+            self.font_size(),
+            &self.controls,
+        );
+        let actions = Action::ALL.len();
+        match event {
+            Some(MenuEvent::Pressed(index)) if index < actions => {
+                self.rebinding = Some(Action::ALL[index])
+            }
+            Some(MenuEvent::Pressed(index)) if index == actions => {
+                self.settings.bindings = KeyBindings::default();
+                self.apply_settings();
+                self.save_settings();
+            }
+            Some(MenuEvent::Pressed(_) | MenuEvent::Back) => self.state = GameState::Settings,
+            _ => {}
+        }
+    }
+
+    fn state_level_completed(&mut self) {
+        let next_level = &self.levels[self.level_index + 1];
+        draw_title_text(
+            &format!(
+                "Level {} cleared! Next: {} - Press {}",
+                self.level_index + 1,
+                next_level.name,
+                self.keys_for(Action::Launch)
+            ),
+            self.font,
+        );
+        if self.screen_ready() && (self.controls.released(Action::Launch) || self.controls.tapped())
+        {
+            self.state = GameState::LaunchNewBall;
+            self.next_level();
+        }
+    }
+
+    fn state_campaign_completed(&mut self) {
+        self.draw_run_end("CAMPAIGN COMPLETE");
+    }
+
     fn state_game_over(&mut self) {
-        draw_title_text(&format!("GAME OVER - Score: {}", self.score), self.font);
-        if is_key_down(KeyCode::Space) {
+        self.draw_run_end("GAME OVER");
+    }
+
+    /// Final score and high score table shown when a run is over.
+    fn draw_run_end(&mut self, title: &str) {
+        self.draw_screen_title(title);
+        let font_size = self.font_size();
+        ui::draw_centered_text(
+            &format!("Score: {}", self.world.score),
+            screen_height() * 0.33f32,
+            self.font,
+            font_size,
+            BLACK,
+        );
+        self.draw_high_scores(screen_height() * 0.42f32);
+        ui::draw_centered_text(
+            &format!("Press {} to continue", self.keys_for(Action::Launch)),
+            screen_height() * 0.92f32,
+            self.font,
+            font_size,
+            GRAY,
+        );
+        if self.screen_ready() && (self.controls.released(Action::Launch) || self.controls.tapped())
+        {
             self.state = GameState::Menu;
-            self.reset();
+            self.new_high_score = None;
+        }
+    }
+
+    /// Whether the current screen has been up for `MIN_SCREEN_TIME`. Input
+    /// held before then is ignored until it is let go.
+    fn screen_ready(&mut self) -> bool {
+        let ready = get_time() - self.state_entered >= MIN_SCREEN_TIME;
+        if !ready {
+            self.controls.consume();
         }
+        ready
     }
 
     pub fn frame(&mut self) {
+        self.controls.update();
+        // Frames can be shorter than a step; a launch press no step has seen
+        // yet carries over to the next frame.
+        let unseen_press = self.input.launch_pressed;
+        // Read every frame so moving the mouse in a menu also switches to it.
+        self.input = self.controls.capture();
+        self.input.launch_pressed |= unseen_press;
+        let previous = self.state;
         match self.state {
             GameState::Menu => {
                 self.state_menu();
@@ -336,29 +1105,142 @@ impl<'a> Game {
             GameState::LevelCompleted => {
                 self.state_level_completed();
             }
+            GameState::CampaignCompleted => {
+                self.state_campaign_completed();
+            }
             GameState::GameOver => {
                 self.state_game_over();
             }
             GameState::LaunchNewBall => {
                 self.state_launch_new_ball();
             }
+            GameState::Paused => {
+                self.state_paused();
+            }
+            GameState::Settings => {
+                self.state_settings();
+            }
+            GameState::LevelSelect => {
+                self.state_level_select();
+            }
+            GameState::HighScores => {
+                self.state_high_scores();
+            }
+            GameState::Controls => {
+                self.state_controls();
+            }
+            GameState::EnterName => {
+                self.state_enter_name();
+            }
+            GameState::Replay => {
+                self.state_replay();
+            }
+        }
+        // Every screen starts with a fresh press. Losing or launching a ball
+        // stays on the same screen, so held keys keep steering and firing.
+        if self.state != previous {
+            self.input.launch_pressed = false;
+        }
+        let playing = [GameState::Game, GameState::LaunchNewBall];
+        if self.state != previous && !(playing.contains(&previous) && playing.contains(&self.state))
+        {
+            self.controls.consume();
+            self.state_entered = get_time();
+        }
+    }
+}
+
+const LEVELS: [(&str, &str); 3] = [
+    ("level_01.txt", include_str!("../res/levels/level_01.txt")),
+    ("level_02.txt", include_str!("../res/levels/level_02.txt")),
+    ("level_03.txt", include_str!("../res/levels/level_03.txt")),
+];
+
+fn load_levels() -> Result<Vec<Level>, String> {
+    LEVELS
+        .iter()
+        .map(|(file_name, source)| {
+            Level::parse(source).map_err(|error| format!("{}:{}", file_name, error))
+        })
+        .collect()
+}
+
+async fn show_level_error(message: String) {
+    eprintln!("{}", message);
+    let font = load_ttf_font_from_bytes(include_bytes!("../res/Roboto-Regular.ttf")).unwrap();
+    while !is_key_down(KeyCode::Escape) {
+        clear_background(WHITE);
+        draw_title_text(&message, font);
+        next_frame().await
+    }
+}
+
+/// The value following `name` on the command line.
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|arg| arg == name)?;
+    args.next()
+}
+
+fn read_replay(path: &str) -> Result<Replay, String> {
+    let text = std::fs::read_to_string(path).map_err(|error| format!("{}: {}", path, error))?;
+    Replay::parse(&text).map_err(|error| format!("{}:{}", path, error))
+}
+
+/// Plays a replay without a window and exits with an error if it doesn't
+/// end with the recorded score and state hash.
+fn verify_replay(path: &str) {
+    let result = read_replay(path).and_then(|replay| {
+        let levels = load_levels()?;
+        let scale = Scale::for_width(SCALE, SCREEN_SCALE_FACTOR, replay.bounds.w);
+        let mut world = World::new(replay.bounds, scale, &levels[0], replay.seed);
+        let dt = FixedTimestep::new(replay.tick_rate).dt;
+        replay::verify(&replay, &mut world, &levels, dt)
+    });
+    match result {
+        Ok((score, hash)) => println!("{}: ok, score {}, hash {:016x}", path, score, hash),
+        Err(error) => {
+            eprintln!("{}: {}", path, error);
+            std::process::exit(1);
         }
     }
 }
 
-#[macroquad::main("Breakdown")]
-async fn main() {
+fn main() {
+    if std::env::args().any(|arg| arg == "--bench-collision") {
+        return bench::run_collision_benchmark();
+    }
+    if let Some(path) = arg_value("--verify-replay") {
+        return verify_replay(&path);
+    }
+    macroquad::Window::new("Breakdown", run());
+}
+
+async fn run() {
+    let levels = match load_levels() {
+        Ok(levels) => levels,
+        Err(message) => return show_level_error(message).await,
+    };
     let scale: Scale = Scale::new(SCALE, SCREEN_SCALE_FACTOR);
-    let mut game = Game::new(scale).await;
+    let mut game = Game::new(scale, levels).await;
+    game.record_path = arg_value("--record");
+    if let Some(path) = arg_value("--replay") {
+        match read_replay(&path) {
+            Ok(replay) => game.start_replay(&replay),
+            Err(error) => eprintln!("{}", error),
+        }
+    }
+    // Closing the window goes through `Game::exit` so the run can be saved.
+    prevent_quit();
 
     loop {
         clear_background(WHITE);
 
-        if is_key_down(KeyCode::Escape) {
+        game.frame();
+        if game.quit || is_quit_requested() {
+            game.exit();
             break;
         }
-
-        game.frame();
         next_frame().await
     }
 }
@@ -4,6 +4,8 @@ pub struct GameAudio {
     pub hit_block: macroquad::audio::Sound,
     pub hit_player: macroquad::audio::Sound,
     pub hit_floor: macroquad::audio::Sound,
+    pub shoot: macroquad::audio::Sound,
+    pub volume: f32,
 }
 
 impl GameAudio {
@@ -18,13 +20,17 @@ impl GameAudio {
             hit_floor: load_sound_from_bytes(include_bytes!("../../res/audio/hit_floor.wav"))
                 .await
                 .unwrap(),
+            shoot: load_sound_from_bytes(include_bytes!("../../res/audio/shoot.wav"))
+                .await
+                .unwrap(),
+            volume: 0.4,
         }
     }
 
     pub fn play_single(&self, sound: macroquad::audio::Sound) {
         let params = PlaySoundParams {
             looped: false,
-            volume: 0.4,
+            volume: self.volume,
         };
         play_sound(sound, params);
     }
@@ -0,0 +1,128 @@
+use crate::controls::{self, Action, KeyBindings};
+use crate::game::input::PointerControl;
+use crate::game::timestep::{DEFAULT_TICK_RATE, MAX_TICK_RATE, MIN_TICK_RATE};
+use crate::storage;
+
+const STORAGE_KEY: &str = "settings";
+
+/// Particle amounts offered in the settings, with their density.
+pub const PARTICLE_LEVELS: [(&str, f32); 3] = [("off", 0f32), ("normal", 1f32), ("lots", 2f32)];
+
+/// Player preferences that apply across runs.
+#[derive(Clone)]
+pub struct Settings {
+    pub volume: f32,
+    /// Fire the space invader cannons without holding the fire key.
+    pub auto_fire: bool,
+    /// Index into `PARTICLE_LEVELS`.
+    pub particle_level: usize,
+    /// See `PointerControl`.
+    pub pointer_smoothing: f32,
+    pub pointer_speed: f32,
+    /// Simulation ticks per second. Only read from the settings file, and
+    /// only when the game starts.
+    pub tick_rate: f32,
+    pub bindings: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            volume: 0.4,
+            auto_fire: false,
+            particle_level: 1,
+            pointer_smoothing: PointerControl::default().smoothing,
+            pointer_speed: PointerControl::default().max_speed,
+            tick_rate: DEFAULT_TICK_RATE,
+            bindings: KeyBindings::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn particle_density(&self) -> f32 {
+        PARTICLE_LEVELS[self.particle_level.min(PARTICLE_LEVELS.len() - 1)].1
+    }
+
+    /// Loads the saved settings. Missing or unreadable values keep their
+    /// defaults.
+    pub fn load() -> Self {
+        let mut settings = Settings::default();
+        if let Some(text) = storage::load(STORAGE_KEY) {
+            for line in text.lines() {
+                if let Some((key, value)) = line.split_once(':') {
+                    settings.set(key.trim(), value.trim());
+                }
+            }
+        }
+        settings
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match key {
+            "volume" => {
+                if let Ok(volume) = value.parse::<f32>() {
+                    self.volume = volume.clamp(0f32, 1f32);
+                }
+            }
+            "auto_fire" => {
+                if let Ok(auto_fire) = value.parse() {
+                    self.auto_fire = auto_fire;
+                }
+            }
+            "particles" => {
+                if let Some(level) = PARTICLE_LEVELS.iter().position(|(name, _)| *name == value) {
+                    self.particle_level = level;
+                }
+            }
+            "pointer_smoothing" => {
+                if let Ok(smoothing) = value.parse::<f32>() {
+                    self.pointer_smoothing = smoothing.max(0f32);
+                }
+            }
+            "pointer_speed" => {
+                if let Ok(speed) = value.parse::<f32>() {
+                    self.pointer_speed = speed.max(0.1);
+                }
+            }
+            "tick_rate" => {
+                if let Ok(tick_rate) = value.parse::<f32>() {
+                    self.tick_rate = tick_rate.clamp(MIN_TICK_RATE, MAX_TICK_RATE);
+                }
+            }
+            _ => {
+                let Some(action) = key.strip_prefix("key.").and_then(Action::from_name) else {
+                    return;
+                };
+                let keys: Option<Vec<_>> = value
+                    .split_whitespace()
+                    .map(controls::key_from_name)
+                    .collect();
+                if let Some(keys) = keys {
+                    self.bindings.set(action, keys);
+                }
+            }
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let mut lines = vec![
+            format!("volume: {}", self.volume),
+            format!("auto_fire: {}", self.auto_fire),
+            format!("particles: {}", PARTICLE_LEVELS[self.particle_level].0),
+            format!("pointer_smoothing: {}", self.pointer_smoothing),
+            format!("pointer_speed: {}", self.pointer_speed),
+            format!("tick_rate: {}", self.tick_rate),
+        ];
+        for action in Action::ALL {
+            let keys: Vec<String> = self
+                .bindings
+                .keys(action)
+                .iter()
+                .map(|key| controls::key_name(*key))
+                .collect();
+            lines.push(format!("key.{}: {}", action.name(), keys.join(" ")));
+        }
+        storage::save(STORAGE_KEY, &(lines.join("\n") + "\n"))
+    }
+}
@@ -0,0 +1,101 @@
+//! Small text blobs that survive restarts: files in the user's data directory
+//! on native builds, and the browser's local storage on wasm.
+
+#[cfg(not(target_arch = "wasm32"))]
+mod native {
+    use std::fs;
+    use std::path::PathBuf;
+
+    const APP_DIR: &str = "breakdown";
+
+    fn data_dir() -> Option<PathBuf> {
+        let base = match std::env::var_os("XDG_DATA_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => match std::env::var_os("APPDATA") {
+                Some(dir) => PathBuf::from(dir),
+                None => {
+                    let home = PathBuf::from(std::env::var_os("HOME")?);
+                    match cfg!(target_os = "macos") {
+                        true => home.join("Library").join("Application Support"),
+                        false => home.join(".local").join("share"),
+                    }
+                }
+            },
+        };
+        Some(base.join(APP_DIR))
+    }
+
+    fn path(key: &str) -> Option<PathBuf> {
+        Some(data_dir()?.join(format!("{}.txt", key)))
+    }
+
+    pub fn load(key: &str) -> Option<String> {
+        fs::read_to_string(path(key)?).ok()
+    }
+
+    /// Writes to a temporary file first, so a crash mid-write never leaves a
+    /// truncated file behind.
+    pub fn save(key: &str, contents: &str) -> Result<(), String> {
+        let path = path(key).ok_or("no data directory found")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|error| error.to_string())?;
+        }
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents).map_err(|error| error.to_string())?;
+        fs::rename(&temp, &path).map_err(|error| error.to_string())
+    }
+
+    pub fn remove(key: &str) {
+        if let Some(path) = path(key) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use native::{load, remove, save};
+
+// Implemented by `res/web/storage.js`.
+#[cfg(target_arch = "wasm32")]
+extern "C" {
+    fn breakdown_storage_len(key: *const u8, key_len: u32) -> i32;
+    fn breakdown_storage_get(key: *const u8, key_len: u32, buffer: *mut u8, buffer_len: u32);
+    fn breakdown_storage_set(key: *const u8, key_len: u32, value: *const u8, value_len: u32);
+    fn breakdown_storage_remove(key: *const u8, key_len: u32);
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn load(key: &str) -> Option<String> {
+    unsafe {
+        let len = breakdown_storage_len(key.as_ptr(), key.len() as u32);
+        if len < 0 {
+            return None;
+        }
+        let mut buffer = vec![0u8; len as usize];
+        breakdown_storage_get(
+            key.as_ptr(),
+            key.len() as u32,
+            buffer.as_mut_ptr(),
+            buffer.len() as u32,
+        );
+        String::from_utf8(buffer).ok()
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn save(key: &str, contents: &str) -> Result<(), String> {
+    unsafe {
+        breakdown_storage_set(
+            key.as_ptr(),
+            key.len() as u32,
+            contents.as_ptr(),
+            contents.len() as u32,
+        );
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "wasm32")]
+pub fn remove(key: &str) {
+    unsafe { breakdown_storage_remove(key.as_ptr(), key.len() as u32) }
+}
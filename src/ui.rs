@@ -0,0 +1,291 @@
+use crate::controls::{Action, Controls};
+use crate::gamepad::PadButton;
+use macroquad::prelude::*;
+
+const ITEM_SPACING: f32 = 1.6;
+// Slider bar size, relative to the font size
+const SLIDER_WIDTH: f32 = 6f32;
+const SLIDER_HEIGHT: f32 = 0.5;
+
+pub enum Widget {
+    Button {
+        label: String,
+        enabled: bool,
+    },
+    Slider {
+        label: String,
+        value: f32,
+        min: f32,
+        max: f32,
+        step: f32,
+    },
+    Toggle {
+        label: String,
+        value: bool,
+    },
+    Selector {
+        label: String,
+        options: Vec<String>,
+        index: usize,
+    },
+}
+
+impl Widget {
+    pub fn button(label: &str) -> Self {
+        Widget::Button {
+            label: label.to_string(),
+            enabled: true,
+        }
+    }
+
+    fn enabled(&self) -> bool {
+        !matches!(self, Widget::Button { enabled: false, .. })
+    }
+
+    fn text(&self) -> String {
+        match self {
+            Widget::Button { label, .. } => label.clone(),
+            Widget::Slider { label, .. } => label.clone(),
+            Widget::Toggle { label, value } => format!(
+                "{}: {}",
+                label,
+                match value {
+                    true => "on",
+                    false => "off",
+                }
+            ),
+            Widget::Selector {
+                label,
+                options,
+                index,
+            } => format!("{}: < {} >", label, options[*index]),
+        }
+    }
+
+    /// Steps the value by `direction` (-1 or 1) and returns whether it
+    /// changed. Buttons have no value.
+    fn adjust(&mut self, direction: i32) -> bool {
+        match self {
+            Widget::Button { .. } => false,
+            Widget::Slider {
+                value,
+                min,
+                max,
+                step,
+                ..
+            } => {
+                let new_value = (*value + direction as f32 * *step).clamp(*min, *max);
+                let changed = new_value != *value;
+                *value = new_value;
+                changed
+            }
+            Widget::Toggle { value, .. } => {
+                *value = !*value;
+                true
+            }
+            Widget::Selector { options, index, .. } => {
+                let len = options.len() as i32;
+                *index = (*index as i32 + direction).rem_euclid(len) as usize;
+                len > 1
+            }
+        }
+    }
+}
+
+pub enum MenuEvent {
+    /// A button was activated.
+    Pressed(usize),
+    /// The value of a slider, toggle or selector changed.
+    Changed,
+    /// The player backed out of the menu.
+    Back,
+}
+
+/// A vertical list of widgets centered on the screen, navigated with the
+/// keyboard (up / down or W / S, then the move, confirm and back actions),
+/// the mouse or a gamepad.
+pub struct Menu {
+    pub selected: usize,
+}
+
+impl Menu {
+    pub fn new() -> Self {
+        Self { selected: 0 }
+    }
+
+    /// Handles this frame's input and draws the widgets starting at `top`.
+    /// Widget values are updated in place.
+    pub fn update(
+        &mut self,
+        widgets: &mut [Widget],
+        top: f32,
+        font: Font,
+        font_size: u16,
+        controls: &Controls,
+    ) -> Option<MenuEvent> {
+        let pad = &controls.gamepads;
+        if !widgets.iter().any(Widget::enabled) {
+            return None;
+        }
+        if !widgets[self.selected.min(widgets.len() - 1)].enabled() {
+            self.move_selection(widgets, 1);
+        }
+        self.selected = self.selected.min(widgets.len() - 1);
+
+        let mut event = None;
+        if is_key_pressed(KeyCode::Down)
+            || is_key_pressed(KeyCode::S)
+            || pad.pressed(PadButton::Down)
+        {
+            self.move_selection(widgets, 1);
+        }
+        if is_key_pressed(KeyCode::Up) || is_key_pressed(KeyCode::W) || pad.pressed(PadButton::Up) {
+            self.move_selection(widgets, -1);
+        }
+        let direction = match (
+            controls.pressed(Action::MoveLeft),
+            controls.pressed(Action::MoveRight),
+        ) {
+            (true, false) => -1,
+            (false, true) => 1,
+            _ => 0,
+        };
+        if direction != 0 && widgets[self.selected].adjust(direction) {
+            event = Some(MenuEvent::Changed);
+        }
+        if controls.pressed(Action::Confirm) {
+            event = self.activate(widgets);
+        }
+        if controls.pressed(Action::Back) {
+            event = Some(MenuEvent::Back);
+        }
+
+        let line_height = font_size as f32 * ITEM_SPACING;
+        let slider_size = vec2(
+            font_size as f32 * SLIDER_WIDTH,
+            font_size as f32 * SLIDER_HEIGHT,
+        );
+        let gap = font_size as f32 * 0.5f32;
+        let mouse = Vec2::from(mouse_position());
+        for index in 0..widgets.len() {
+            let text = widgets[index].text();
+            let dims = measure_text(&text, Some(font), font_size, 1.0);
+            let width = match widgets[index] {
+                Widget::Slider { .. } => dims.width + gap + slider_size.x,
+                _ => dims.width,
+            };
+            let x = screen_width() * 0.5f32 - width * 0.5f32;
+            let y = top + index as f32 * line_height;
+            let area = Rect::new(x, y - dims.offset_y, width, line_height);
+            if widgets[index].enabled() && area.contains(mouse) {
+                self.selected = index;
+                if is_mouse_button_pressed(MouseButton::Left) {
+                    event = self.activate(widgets);
+                }
+            }
+
+            let color = match (widgets[index].enabled(), index == self.selected) {
+                (false, _) => LIGHTGRAY,
+                (true, true) => RED,
+                (true, false) => BLACK,
+            };
+            draw_text_ex(
+                &text,
+                x,
+                y,
+                TextParams {
+                    font,
+                    font_size,
+                    color,
+                    ..Default::default()
+                },
+            );
+
+            if let Widget::Slider {
+                value,
+                min,
+                max,
+                step,
+                ..
+            } = &mut widgets[index]
+            {
+                let bar = Rect::new(
+                    x + dims.width + gap,
+                    y - dims.offset_y * 0.5f32 - slider_size.y * 0.5f32,
+                    slider_size.x,
+                    slider_size.y,
+                );
+                // Dragging on the bar sets the value directly.
+                if is_mouse_button_down(MouseButton::Left) && bar.contains(mouse) {
+                    let fraction = (mouse.x - bar.x) / bar.w;
+                    let new_value = *min + (*max - *min) * fraction;
+                    let new_value = ((new_value / *step).round() * *step).clamp(*min, *max);
+                    if new_value != *value {
+                        *value = new_value;
+                        event = Some(MenuEvent::Changed);
+                    }
+                }
+                let fill = (*value - *min) / (*max - *min);
+                draw_rectangle(bar.x, bar.y, bar.w * fill, bar.h, color);
+                draw_rectangle_lines(bar.x, bar.y, bar.w, bar.h, 2f32, color);
+            }
+        }
+        event
+    }
+
+    fn move_selection(&mut self, widgets: &[Widget], direction: i32) {
+        let len = widgets.len() as i32;
+        let mut index = self.selected as i32;
+        for _ in 0..len {
+            index = (index + direction).rem_euclid(len);
+            if widgets[index as usize].enabled() {
+                break;
+            }
+        }
+        self.selected = index as usize;
+    }
+
+    fn activate(&mut self, widgets: &mut [Widget]) -> Option<MenuEvent> {
+        let widget = &mut widgets[self.selected];
+        match widget {
+            Widget::Button { enabled: true, .. } => Some(MenuEvent::Pressed(self.selected)),
+            Widget::Button { enabled: false, .. } => None,
+            // Activating a slider at its maximum wraps it around to the minimum.
+            Widget::Slider {
+                value, min, max, ..
+            } if *value >= *max => {
+                *value = *min;
+                Some(MenuEvent::Changed)
+            }
+            _ => widget.adjust(1).then_some(MenuEvent::Changed),
+        }
+    }
+}
+
+/// Covers the whole screen with a translucent layer so menus stand out from
+/// the board behind them.
+pub fn dim_screen() {
+    draw_rectangle(
+        0f32,
+        0f32,
+        screen_width(),
+        screen_height(),
+        Color::new(1f32, 1f32, 1f32, 0.75f32),
+    );
+}
+
+/// Draws `text` horizontally centered with its baseline at `y`.
+pub fn draw_centered_text(text: &str, y: f32, font: Font, font_size: u16, color: Color) {
+    let dims = measure_text(text, Some(font), font_size, 1.0);
+    draw_text_ex(
+        text,
+        screen_width() * 0.5f32 - dims.width * 0.5f32,
+        y,
+        TextParams {
+            font,
+            font_size,
+            color,
+            ..Default::default()
+        },
+    );
+}